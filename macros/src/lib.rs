@@ -0,0 +1,188 @@
+#![warn(clippy::pedantic)]
+
+//! Procedural macros for the `workshop-rustlab-2022` crate.
+//!
+//! This crate is not meant to be used directly, the macros are re-exported by the main crate.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    braced,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    Attribute, Ident, Token, Visibility,
+};
+
+/// The `ServerField` variants, associated to the name of the field in `RawEntry`.
+///
+/// The names are not always the _snake case_ version of the variants, therefore it is necessary to
+/// keep an explicit mapping. The tests of the main crate check it against `ServerField::ALL`.
+const FIELDS: [(&str, &str); 27] = [
+    ("Id", "id"),
+    ("GeoPoint2d", "geo_point_2d"),
+    ("GeoShape", "geo_shape"),
+    ("Name", "name"),
+    ("Etichetta", "etichetta"),
+    ("Notetesto", "notetesto"),
+    ("Numeroantico", "numeroantico"),
+    ("Numeromoderno", "numeromoderno"),
+    ("Link1", "link1"),
+    ("Link2", "link2"),
+    ("Link3", "link3"),
+    ("Piani", "piani"),
+    ("Arcate", "arcate"),
+    ("Architravate", "architravate"),
+    (
+        "ArchitravateConColonneDiLegno",
+        "architravate_con_colonne_di_legno",
+    ),
+    ("Archivolti", "archivolti"),
+    ("Modiglioni", "modiglioni"),
+    ("MensoloniArchitravati", "mensoloni_architravati"),
+    ("StallaE", "stalla_e"),
+    ("FienileI", "fienile_i"),
+    ("RimessaE", "rimessa_e"),
+    ("ScuderiaE", "scuderia_e"),
+    (
+        "AttivitaCommercialiProduttive1",
+        "attivita_commerciali_produttive_1",
+    ),
+    (
+        "AttivitaCommercialiProduttive2",
+        "attivita_commerciali_produttive_2",
+    ),
+    (
+        "AttivitaCommercialiProduttive3",
+        "attivita_commerciali_produttive_3",
+    ),
+    (
+        "AttivitaCommercialiProduttive4",
+        "attivita_commerciali_produttive_4",
+    ),
+    (
+        "AttivitaCommercialiProduttive5",
+        "attivita_commerciali_produttive_5",
+    ),
+];
+
+/// The input of the [`projection!`] macro.
+struct ProjectionInput {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    fields: Punctuated<Ident, Token![,]>,
+}
+
+impl Parse for ProjectionInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<Token![struct]>()?;
+        let ident = input.parse()?;
+
+        let content;
+        braced!(content in input);
+        let fields = content.parse_terminated(Ident::parse, Token![,])?;
+
+        Ok(Self {
+            attrs,
+            vis,
+            ident,
+            fields,
+        })
+    }
+}
+
+/// Creates a struct containing only the specified `ServerField`s.
+///
+/// The generated struct has one public field for each `ServerField`, named and typed like the
+/// corresponding field of `RawEntry`. It implements `Deserialize` and `Projection`, therefore
+/// `Projection::FIELDS` can be used to build a `ServerQuery` which always matches the struct.
+///
+/// ```ignore
+/// projection! {
+///     #[derive(Debug)]
+///     pub struct CustomEntry {
+///         Name,
+///         GeoPoint2d,
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn projection(input: TokenStream) -> TokenStream {
+    let ProjectionInput {
+        attrs,
+        vis,
+        ident,
+        fields,
+    } = parse_macro_input!(input as ProjectionInput);
+
+    if fields.is_empty() {
+        return syn::Error::new(
+            ident.span(),
+            "a projection must contain at least one field, an empty set of fields means all \
+             the fields of `Entry`",
+        )
+        .into_compile_error()
+        .into();
+    }
+
+    let mut variants = Vec::with_capacity(fields.len());
+    let mut field_names = Vec::with_capacity(fields.len());
+    let mut errors: Option<syn::Error> = None;
+    let mut push_error = |error: syn::Error| match &mut errors {
+        Some(errors) => errors.combine(error),
+        None => errors = Some(error),
+    };
+
+    for variant in &fields {
+        let Some((_, field_name)) = FIELDS
+            .iter()
+            .find(|(variant_name, _)| variant == variant_name)
+        else {
+            push_error(syn::Error::new(
+                variant.span(),
+                format!("`{variant}` is not a variant of `ServerField`"),
+            ));
+            continue;
+        };
+
+        if variants.contains(&variant) {
+            push_error(syn::Error::new(
+                variant.span(),
+                format!("`{variant}` is specified more than once"),
+            ));
+            continue;
+        }
+
+        variants.push(variant);
+        field_names.push(Ident::new(field_name, variant.span()));
+    }
+
+    if let Some(errors) = errors {
+        return errors.into_compile_error().into();
+    }
+
+    let krate = Ident::new("workshop_rustlab_2022", Span::call_site());
+    let serde_path = format!("::{krate}::__private::serde");
+
+    quote! {
+        #(#attrs)*
+        #[derive(::#krate::__private::serde::Deserialize)]
+        #[serde(crate = #serde_path)]
+        #vis struct #ident {
+            #(
+                pub #field_names: ::#krate::database::field_type::#variants,
+            )*
+        }
+
+        impl ::#krate::database::Projection for #ident {
+            const FIELDS: &'static [::#krate::database::ServerField] = &[
+                #(::#krate::database::ServerField::#variants,)*
+            ];
+        }
+    }
+    .into()
+}
@@ -0,0 +1,393 @@
+#![warn(clippy::pedantic)]
+
+//! Checks a JSON database against the [`Entry`] model, reporting all the issues found.
+//!
+//! Usage: `db-lint [PATH]`. If `PATH` is omitted, the database used by the server is checked.
+//!
+//! The exit code is non-zero if any issue is found.
+
+use std::{
+    collections::{hash_map, HashMap},
+    env,
+    fmt::{self, Display},
+    fs,
+    ops::Not,
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use serde_json::Value;
+use url::Url;
+use workshop_rustlab_2022::database::{
+    geo_shape::{FeatureGeometry, Position},
+    Entry, EntryError, GeoShape, RawEntry, ServerField,
+};
+
+const DEFAULT_DATABASE_PATH: &str = "assets/database.json";
+
+/// A problem found in a specific entry of the database.
+#[derive(Debug)]
+struct Issue {
+    /// The index of the entry in the database.
+    index: usize,
+
+    /// The field containing the problem, if any.
+    field: Option<ServerField>,
+    message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {}", self.index)?;
+        if let Some(field) = self.field {
+            write!(f, ", field `{}`", field.to_str())?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+fn main() -> ExitCode {
+    let path = env::args_os()
+        .nth(1)
+        .map_or_else(|| PathBuf::from(DEFAULT_DATABASE_PATH), PathBuf::from);
+
+    let database = match fs::read_to_string(&path) {
+        Ok(database) => database,
+        Err(error) => {
+            eprintln!("unable to read {}: {error}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let values: Vec<Value> = match serde_json::from_str(&database) {
+        Ok(values) => values,
+        Err(error) => {
+            eprintln!("{} is not a JSON array: {error}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let entries_len = values.len();
+
+    let mut issues = Vec::new();
+    let entries: Vec<_> = values
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, value)| match serde_json::from_value(value) {
+            Ok(entry) => Some((index, entry)),
+            Err(error) => {
+                issues.push(Issue {
+                    index,
+                    field: None,
+                    message: format!("cannot be deserialized: {error}"),
+                });
+                None
+            }
+        })
+        .collect();
+
+    for (index, entry) in &entries {
+        check_model(*index, entry, &mut issues);
+        check_links(*index, entry, &mut issues);
+        check_geometry(*index, entry, &mut issues);
+    }
+    check_duplicate_names(&entries, &mut issues);
+    check_numbering(&entries, &mut issues);
+
+    issues.sort_by_key(|issue| issue.index);
+    for issue in &issues {
+        println!("{issue}");
+    }
+    println!(
+        "{entries_len} entries checked, {} issues found",
+        issues.len()
+    );
+
+    if issues.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Checks that the entry can be converted to the typed [`Entry`].
+fn check_model(index: usize, entry: &RawEntry, issues: &mut Vec<Issue>) {
+    if let Err(EntryError(errors)) = Entry::try_from(entry.clone()) {
+        issues.extend(errors.into_iter().map(|error| Issue {
+            index,
+            field: Some(error.field),
+            message: format!("{} ({:?})", error.kind, error.value),
+        }));
+    }
+}
+
+/// Checks that the links, when valid URLs, point to web pages.
+fn check_links(index: usize, entry: &RawEntry, issues: &mut Vec<Issue>) {
+    let links = [
+        (ServerField::Link1, &entry.link1),
+        (ServerField::Link2, &entry.link2),
+        (ServerField::Link3, &entry.link3),
+    ];
+
+    for (field, link) in links {
+        let Ok(url) = Url::parse(link) else {
+            // Invalid URLs are already reported by `check_model`.
+            continue;
+        };
+
+        if matches!(url.scheme(), "http" | "https").not() {
+            issues.push(Issue {
+                index,
+                field: Some(field),
+                message: format!("unexpected URL scheme {:?}", url.scheme()),
+            });
+        }
+    }
+}
+
+/// Checks the polygons of the shape, and that `geo_point_2d` lies inside them.
+fn check_geometry(index: usize, entry: &RawEntry, issues: &mut Vec<Issue>) {
+    let GeoShape::Feature(feature) = &entry.geo_shape;
+
+    let errors = feature.geometry.validate();
+    let is_valid = errors.is_empty();
+    issues.extend(errors.into_iter().map(|error| Issue {
+        index,
+        field: Some(ServerField::GeoShape),
+        message: error.to_string(),
+    }));
+
+    let has_polygons = matches!(
+        feature.geometry,
+        FeatureGeometry::Polygon { .. } | FeatureGeometry::MultiPolygon { .. }
+    );
+    let point = Position::new(entry.geo_point_2d.lon, entry.geo_point_2d.lat);
+    if is_valid && has_polygons && feature.geometry.contains(point).not() {
+        issues.push(Issue {
+            index,
+            field: Some(ServerField::GeoPoint2d),
+            message: format!(
+                "point ({}, {}) is outside of `geo_shape`",
+                point.lon, point.lat
+            ),
+        });
+    }
+}
+
+/// Checks that every non-empty name is used only once.
+fn check_duplicate_names(entries: &[(usize, RawEntry)], issues: &mut Vec<Issue>) {
+    let mut first_indices = HashMap::new();
+    for (index, entry) in entries {
+        if entry.name.is_empty() {
+            continue;
+        }
+
+        match first_indices.entry(entry.name.as_str()) {
+            hash_map::Entry::Vacant(vacant) => {
+                vacant.insert(*index);
+            }
+            hash_map::Entry::Occupied(occupied) => issues.push(Issue {
+                index: *index,
+                field: Some(ServerField::Name),
+                message: format!(
+                    "name {:?} is already used by entry {}",
+                    entry.name,
+                    occupied.get()
+                ),
+            }),
+        }
+    }
+}
+
+/// Checks that the same `numeroantico` always corresponds to the same `numeromoderno`.
+fn check_numbering(entries: &[(usize, RawEntry)], issues: &mut Vec<Issue>) {
+    let mut modern_numbers = HashMap::new();
+    for (index, entry) in entries {
+        if entry.numeroantico.is_empty() {
+            continue;
+        }
+
+        let (first_index, numeromoderno) = *modern_numbers
+            .entry(entry.numeroantico.as_str())
+            .or_insert((*index, entry.numeromoderno.as_str()));
+
+        if numeromoderno != entry.numeromoderno {
+            issues.push(Issue {
+                index: *index,
+                field: Some(ServerField::Numeromoderno),
+                message: format!(
+                    "numeroantico {:?} corresponds to numeromoderno {numeromoderno:?} in entry \
+                     {first_index}, but it is {:?} here",
+                    entry.numeroantico, entry.numeromoderno,
+                ),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use workshop_rustlab_2022::database::{geo_shape::Feature, raw::FLAG_SET, GeoPoint2d};
+
+    use super::*;
+
+    fn entry() -> RawEntry {
+        let square = vec![
+            Position::new(11.3, 44.4),
+            Position::new(11.4, 44.4),
+            Position::new(11.4, 44.5),
+            Position::new(11.3, 44.5),
+            Position::new(11.3, 44.4),
+        ];
+
+        RawEntry {
+            id: None,
+            geo_point_2d: GeoPoint2d {
+                lon: 11.34,
+                lat: 44.49,
+            },
+            geo_shape: GeoShape::Feature(Feature {
+                geometry: FeatureGeometry::Polygon {
+                    coordinates: vec![square],
+                },
+                properties: None,
+                bbox: None,
+            }),
+            name: "Via Zamboni".to_owned(),
+            etichetta: String::new(),
+            notetesto: String::new(),
+            numeroantico: "1024".to_owned(),
+            numeromoderno: "33".to_owned(),
+            link1: "https://example.com/photo.jpg".to_owned(),
+            link2: String::new(),
+            link3: String::new(),
+            piani: "3".to_owned(),
+            arcate: String::new(),
+            architravate: String::new(),
+            architravate_con_colonne_di_legno: String::new(),
+            archivolti: String::new(),
+            modiglioni: String::new(),
+            mensoloni_architravati: String::new(),
+            stalla_e: FLAG_SET.to_owned(),
+            fienile_i: String::new(),
+            rimessa_e: String::new(),
+            scuderia_e: String::new(),
+            attivita_commerciali_produttive_1: "osteria".to_owned(),
+            attivita_commerciali_produttive_2: String::new(),
+            attivita_commerciali_produttive_3: String::new(),
+            attivita_commerciali_produttive_4: String::new(),
+            attivita_commerciali_produttive_5: String::new(),
+        }
+    }
+
+    fn check_entry(check: fn(usize, &RawEntry, &mut Vec<Issue>), entry: &RawEntry) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        check(0, entry, &mut issues);
+        issues
+    }
+
+    fn locations(issues: &[Issue]) -> Vec<(usize, Option<ServerField>)> {
+        issues
+            .iter()
+            .map(|issue| (issue.index, issue.field))
+            .collect()
+    }
+
+    #[test]
+    fn valid_entry() {
+        let entry = entry();
+        for check in [check_model, check_links, check_geometry] {
+            assert!(check_entry(check, &entry).is_empty());
+        }
+
+        let entries = [(0, entry.clone()), (1, entry)];
+        let mut issues = Vec::new();
+        check_numbering(&entries, &mut issues);
+        assert!(issues.is_empty());
+    }
+
+    #[test]
+    fn model() {
+        let entry = RawEntry {
+            piani: "tre".to_owned(),
+            ..entry()
+        };
+
+        let issues = check_entry(check_model, &entry);
+        assert_eq!(locations(&issues), [(0, Some(ServerField::Piani))]);
+    }
+
+    #[test]
+    fn links() {
+        let entry = RawEntry {
+            link2: "ftp://example.com/photo.jpg".to_owned(),
+            ..entry()
+        };
+
+        let issues = check_entry(check_links, &entry);
+        assert_eq!(locations(&issues), [(0, Some(ServerField::Link2))]);
+    }
+
+    #[test]
+    fn point_outside_of_shape() {
+        let entry = RawEntry {
+            geo_point_2d: GeoPoint2d {
+                lon: 11.5,
+                lat: 44.49,
+            },
+            ..entry()
+        };
+
+        let issues = check_entry(check_geometry, &entry);
+        assert_eq!(locations(&issues), [(0, Some(ServerField::GeoPoint2d))]);
+    }
+
+    #[test]
+    fn invalid_shape() {
+        let mut entry = entry();
+        let GeoShape::Feature(feature) = &mut entry.geo_shape;
+        let FeatureGeometry::Polygon { coordinates } = &mut feature.geometry else {
+            unreachable!()
+        };
+        coordinates[0].pop();
+
+        let issues = check_entry(check_geometry, &entry);
+        assert_eq!(locations(&issues), [(0, Some(ServerField::GeoShape))]);
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let entries = [
+            (0, entry()),
+            (
+                1,
+                RawEntry {
+                    name: String::new(),
+                    ..entry()
+                },
+            ),
+            (2, entry()),
+        ];
+
+        let mut issues = Vec::new();
+        check_duplicate_names(&entries, &mut issues);
+        assert_eq!(locations(&issues), [(2, Some(ServerField::Name))]);
+    }
+
+    #[test]
+    fn numbering() {
+        let entries = [
+            (0, entry()),
+            (
+                1,
+                RawEntry {
+                    numeromoderno: "35".to_owned(),
+                    ..entry()
+                },
+            ),
+        ];
+
+        let mut issues = Vec::new();
+        check_numbering(&entries, &mut issues);
+        assert_eq!(locations(&issues), [(1, Some(ServerField::Numeromoderno))]);
+    }
+}
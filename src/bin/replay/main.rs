@@ -0,0 +1,167 @@
+#![warn(clippy::pedantic)]
+
+//! Replays the requests recorded by a server against another one, reporting the differences in
+//! the status codes and in the bucket headers.
+//!
+//! Usage: `replay RECORDING [URL]`. `RECORDING` is a file written by a server built with
+//! [`Builder::with_recording`], `URL` is the base URL of the server to test and defaults to
+//! `http://127.0.0.1:8080/`.
+//!
+//! The requests are issued one at a time, with the original timing. The bodies are not recorded,
+//! therefore `POST`, `PUT` and `PATCH` requests are skipped. `DELETE` requests are skipped as
+//! well, in order not to destroy the entries of the tested server: the following requests to the
+//! deleted entries can be reported as different. In order to get comparable bucket headers, both
+//! servers should run without a random [`Load`].
+//!
+//! The exit code is non-zero if any difference is found.
+//!
+//! [`Builder::with_recording`]: workshop_rustlab_2022::server::Builder::with_recording
+//! [`Load`]: workshop_rustlab_2022::server::Load
+
+use std::{
+    env, fs,
+    ops::Not,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use reqwest::{Client, Method};
+use tokio::time::{sleep_until, Instant};
+use url::Url;
+use workshop_rustlab_2022::{leaky_bucket::BucketInfo, server::Record};
+
+const DEFAULT_URL: &str = "http://127.0.0.1:8080/";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let mut args = env::args_os().skip(1);
+    let Some(path) = args.next().map(PathBuf::from) else {
+        eprintln!("usage: replay RECORDING [URL]");
+        return ExitCode::FAILURE;
+    };
+    let base_url = args.next().map_or_else(
+        || DEFAULT_URL.to_owned(),
+        |url| url.to_string_lossy().into_owned(),
+    );
+    let base_url = match Url::parse(&base_url) {
+        Ok(base_url) => base_url,
+        Err(error) => {
+            eprintln!("invalid URL {base_url}: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let records = match read_records(&path) {
+        Ok(records) => records,
+        Err(error) => {
+            eprintln!("unable to read {}: {error}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let client = Client::new();
+    let start = Instant::now();
+    let mut replayed = 0;
+    let mut skipped = 0;
+    let mut different = 0;
+
+    for (index, record) in records.iter().enumerate() {
+        let method = match record.method.parse::<Method>() {
+            Ok(method)
+                if [Method::POST, Method::PUT, Method::PATCH, Method::DELETE].contains(&method) =>
+            {
+                skipped += 1;
+                continue;
+            }
+            Ok(method) => method,
+            Err(error) => {
+                eprintln!("request {index}: invalid method: {error}");
+                skipped += 1;
+                continue;
+            }
+        };
+        let url = match base_url.join(&record.uri) {
+            Ok(url) => url,
+            Err(error) => {
+                eprintln!("request {index}: invalid URI: {error}");
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let offset = record
+            .timestamp
+            .duration_since(records[0].timestamp)
+            .unwrap_or_default();
+        sleep_until(start + offset).await;
+
+        replayed += 1;
+        let differences = match client.request(method, url).send().await {
+            Ok(response) => compare(
+                record,
+                response.status().as_u16(),
+                BucketInfo::decode(response.headers()).ok(),
+            ),
+            Err(error) => vec![format!("request failed: {error}")],
+        };
+
+        if differences.is_empty().not() {
+            different += 1;
+            println!(
+                "request {index} ({} {}): {}",
+                record.method,
+                record.uri,
+                differences.join(", ")
+            );
+        }
+    }
+
+    println!("{replayed} requests replayed, {skipped} skipped, {different} with differences");
+    if different == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Reads the records, one JSON per line.
+fn read_records(path: &Path) -> Result<Vec<Record>, String> {
+    let content = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim().is_empty().not())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|error| format!("line {}: {error}", index + 1))
+        })
+        .collect()
+}
+
+/// Returns the differences between the recorded response and the replayed one.
+fn compare(record: &Record, status: u16, bucket: Option<BucketInfo>) -> Vec<String> {
+    let mut differences = Vec::new();
+    if record.status != status {
+        differences.push(format!("status {} -> {status}", record.status));
+    }
+    if record.bucket != bucket {
+        differences.push(format!(
+            "bucket {} -> {}",
+            describe(record.bucket),
+            describe(bucket)
+        ));
+    }
+
+    differences
+}
+
+fn describe(bucket: Option<BucketInfo>) -> String {
+    bucket.map_or_else(
+        || "missing".to_owned(),
+        |bucket| {
+            format!(
+                "{}/{} points leaking {}/s",
+                bucket.points, bucket.capacity, bucket.leak_per_second
+            )
+        },
+    )
+}
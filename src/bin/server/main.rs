@@ -1,185 +1,99 @@
 #![warn(clippy::pedantic)]
 
-mod database;
-mod error;
-
-use std::{convert::Infallible, ops::Not, sync::Arc};
-
-use axum::{
-    http::{header::HeaderName, HeaderValue},
-    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
-    routing::get,
-    Extension, Json, Router,
-};
-use database::PartialEntry;
-use error::Error;
-use rand::{thread_rng, Rng};
-use serde_qs::axum::QsQuery;
-use tokio::{
-    join,
-    sync::{
-        mpsc::{channel, Receiver, Sender},
-        oneshot,
-    },
-};
-use tower_http::trace::TraceLayer;
+//! The example server.
+//!
+//! Usage: `server [LOAD [CHAOS]]`. `LOAD` is an optional JSON file describing the simulated load of
+//! other clients, see [`Load`]. `CHAOS` is an optional JSON file describing the faults to inject,
+//! see [`Chaos`].
+//!
+//! If the `SERVER_RECORDING` environment variable is set, the requests are recorded to the file at
+//! that path, see [`Builder::with_recording`]. If the `SERVER_ADMIN_TOKEN` environment variable
+//! is set, the admin API is enabled with that token, see [`Builder::with_admin_token`]. If the
+//! `SERVER_API_KEYS` environment variable is set, the API keys and their tiers are read from the
+//! JSON file at that path, see [`ApiKeys`]. If the `SERVER_COST_MODEL` environment variable is
+//! set, the costs follow the model in the JSON file at that path, see [`WeightedCost`].
+
+use std::{env, fs, net::SocketAddr};
+
+use tokio::signal;
 use tracing::info;
 use workshop_rustlab_2022::{
-    database::{
-        calc_query_cost, Entry, ServerQuery, DEFAULT_PAGE_SIZE, LEAK_PER_SECOND,
-        MAX_BUCKET_CAPACITY,
-    },
-    leaky_bucket::MaxCapacityError,
-    LeakyBucket, BUCKET_CAPACITY_HEADER, BUCKET_LEAK_PER_SECOND_HEADER, BUCKET_POINTS_HEADER,
+    database::{RawEntry, WeightedCost},
+    server::{ApiKeys, Builder, Chaos, Load},
 };
 
 const RAW_DATABASE: &str = include_str!("../../../assets/database.json");
 
-struct AppStateInner {
-    sender: Sender<Message>,
-}
+/// The write-ahead log containing the changes to the database.
+const WAL_PATH: &str = "data/wal.jsonl";
+
+/// The snapshot of the bucket, saved on shutdown.
+const BUCKET_PATH: &str = "data/bucket.json";
+
+/// The environment variable containing the path of the recording of the requests.
+const RECORDING_VAR: &str = "SERVER_RECORDING";
 
-type AppState = Arc<AppStateInner>;
+/// The environment variable containing the token of the admin API.
+const ADMIN_TOKEN_VAR: &str = "SERVER_ADMIN_TOKEN";
 
-const BUFFER_SIZE: usize = 32;
+/// The environment variable containing the path of the API keys.
+const API_KEYS_VAR: &str = "SERVER_API_KEYS";
+
+/// The environment variable containing the path of the cost model.
+const COST_MODEL_VAR: &str = "SERVER_COST_MODEL";
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let database: Vec<Entry> =
+    let database: Vec<RawEntry> =
         serde_json::from_str(RAW_DATABASE).expect("unable to parse JSON database");
 
-    let (sender, receiver) = channel(BUFFER_SIZE);
-    let app_state = AppStateInner { sender };
-
-    let app = Router::new()
-        .route("/", get(root))
-        .layer(Extension(Arc::new(app_state)))
-        .layer(TraceLayer::new_for_http());
-
-    let axum_future =
-        axum::Server::bind(&"127.0.0.1:8080".parse().unwrap()).serve(app.into_make_service());
-
-    let handler_future = handler(&database, receiver);
+    let mut args = env::args_os().skip(1);
+    let load = args.next().map_or_else(Load::default, |path| {
+        let load = fs::read(path).expect("unable to read the load");
+        serde_json::from_slice(&load).expect("unable to parse the load")
+    });
+    let chaos: Option<Chaos> = args.next().map(|path| {
+        let chaos = fs::read(path).expect("unable to read the chaos");
+        serde_json::from_slice(&chaos).expect("unable to parse the chaos")
+    });
+
+    let mut builder = Builder::new(database)
+        .with_load(load)
+        .with_wal(WAL_PATH)
+        .with_bucket_snapshot(BUCKET_PATH);
+    if let Some(chaos) = chaos {
+        builder = builder.with_chaos(chaos);
+    }
+    if let Some(path) = env::var_os(RECORDING_VAR) {
+        builder = builder.with_recording(path);
+    }
+    if let Ok(token) = env::var(ADMIN_TOKEN_VAR) {
+        builder = builder.with_admin_token(token);
+    }
+    if let Some(path) = env::var_os(API_KEYS_VAR) {
+        let api_keys = fs::read(path).expect("unable to read the API keys");
+        let api_keys: ApiKeys =
+            serde_json::from_slice(&api_keys).expect("unable to parse the API keys");
+        builder = builder.with_api_keys(api_keys);
+    }
+    if let Some(path) = env::var_os(COST_MODEL_VAR) {
+        let cost_model = fs::read(path).expect("unable to read the cost model");
+        let cost_model: WeightedCost =
+            serde_json::from_slice(&cost_model).expect("unable to parse the cost model");
+        builder = builder.with_cost_model(cost_model);
+    }
 
-    info!("Listening on 127.0.0.1:8080");
-    let (axum_result, ()) = join!(axum_future, handler_future);
-    axum_result.unwrap();
-}
+    let server = builder
+        .spawn(SocketAddr::from(([127, 0, 0, 1], 8080)))
+        .expect("unable to start the server");
 
-async fn root(
-    QsQuery(params): QsQuery<ServerQuery>,
-    Extension(state): Extension<AppState>,
-) -> impl IntoResponse {
-    let (replier, receiver) = oneshot::channel();
-    state
-        .sender
-        .send(Message::Query {
-            query: params,
-            replier,
-        })
+    info!("Listening on {}", server.local_addr());
+    signal::ctrl_c()
         .await
-        .unwrap();
-
-    receiver.await.unwrap()
-}
-
-#[derive(Debug)]
-enum Message {
-    Query {
-        query: ServerQuery,
-        replier: oneshot::Sender<Result<(BucketInfo, Response), error::Error>>,
-    },
-}
+        .expect("unable to listen for the shutdown signal");
 
-#[derive(Debug)]
-struct BucketInfo {
-    points: u16,
-    capacity: u16,
-    leak_per_second: u8,
-}
-
-impl IntoResponseParts for BucketInfo {
-    type Error = Infallible;
-
-    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
-        res.headers_mut().extend(
-            [
-                (BUCKET_POINTS_HEADER, self.points),
-                (BUCKET_CAPACITY_HEADER, self.capacity),
-                (BUCKET_LEAK_PER_SECOND_HEADER, self.leak_per_second.into()),
-            ]
-            .into_iter()
-            .map(|(header, value)| {
-                (
-                    Some(HeaderName::from_static(header)),
-                    HeaderValue::from(value),
-                )
-            }),
-        );
-        Ok(res)
-    }
-}
-
-async fn handler(database: &[Entry], mut receiver: Receiver<Message>) {
-    const SPORADIC_POINTS_PROBABILITY: f64 = 0.15;
-    const SPORADIC_POINTS_MAX: u16 = 4;
-
-    let bucket = LeakyBucket::empty(MAX_BUCKET_CAPACITY, LEAK_PER_SECOND);
-    let mut rng = thread_rng();
-
-    while let Some(message) = receiver.recv().await {
-        if rng.gen_bool(SPORADIC_POINTS_PROBABILITY) {
-            bucket.saturating_add(rng.gen_range(1..=SPORADIC_POINTS_MAX));
-        }
-
-        match message {
-            Message::Query { query, replier } => {
-                let cost = calc_query_cost(&query);
-                let capacity = bucket.capacity();
-                let leak_per_second = bucket.leak_per_second();
-                let bucket_points = match bucket.add(cost) {
-                    Ok(points) => points,
-                    Err(MaxCapacityError(points)) => {
-                        let error = Error::NotEnoughCapacity {
-                            request: cost,
-                            points,
-                            capacity,
-                            leak_per_second,
-                        };
-                        replier.send(Err(error)).unwrap();
-                        continue;
-                    }
-                };
-
-                let entries: Vec<_> = database
-                    .chunks(query.page_size.unwrap_or(DEFAULT_PAGE_SIZE).into())
-                    .nth(query.page.unwrap_or(0))
-                    .map(|page| {
-                        page.iter()
-                            .map(|entry| {
-                                query
-                                    .fields
-                                    .is_empty()
-                                    .not()
-                                    .then(|| {
-                                        PartialEntry::from_entry_with_fields(entry, &query.fields)
-                                    })
-                                    .unwrap_or_else(|| PartialEntry::from(entry))
-                            })
-                            .collect()
-                    })
-                    .unwrap_or_default();
-                let response = Json(entries).into_response();
-                let bucket_info = BucketInfo {
-                    points: bucket_points,
-                    capacity,
-                    leak_per_second,
-                };
-
-                replier.send(Ok((bucket_info, response))).unwrap();
-            }
-        }
-    }
+    info!("Shutting down");
+    server.shutdown().await.unwrap();
 }
@@ -0,0 +1,1034 @@
+#![warn(clippy::pedantic)]
+
+//! A client for the example server.
+//!
+//! [`EntryStream`] fetches the entries page by page, keeping a local copy of the [`LeakyBucket`]
+//! of the server in order to request many pages concurrently and to wait instead of being
+//! rejected. Pages can be stored in a
+//! [`PageCache`], even on disk: cached pages are revalidated with `If-None-Match`, and the server
+//! answers `304 Not Modified` at a reduced cost when they did not change. A [`Checkpoint`] of the
+//! stream can be persisted in order to resume it later.
+//!
+//! The costs are estimated with the default [`WeightedCost`], unless the model advertised by the
+//! server is retrieved with [`fetch_cost_model`].
+
+use std::{
+    collections::VecDeque,
+    error,
+    fmt::{self, Display},
+    future::Future,
+    ops::Not,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::SystemTime,
+    vec,
+};
+
+use futures_core::Stream;
+use reqwest::{
+    header::{HeaderMap, ETAG, IF_NONE_MATCH, RETRY_AFTER},
+    StatusCode,
+};
+use serde::de::DeserializeOwned;
+use tokio::time::{sleep, Sleep};
+use url::Url;
+
+use crate::{
+    database::{
+        CostModel, ScaledCost, ServerQuery, WeightedCost, COST_MODEL_PATH, DEFAULT_PAGE_SIZE,
+    },
+    LeakyBucket, BUCKET_REFUND_HEADER,
+};
+
+mod cache;
+mod checkpoint;
+
+pub use cache::{CachedPage, PageCache};
+pub use checkpoint::Checkpoint;
+
+/// The default maximum number of page requests in flight.
+///
+/// See [`EntryStream::with_max_in_flight`].
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+
+/// A stream of the entries returned by the server for a query.
+///
+/// The entries are requested page by page, starting from [`ServerQuery::page`], until an empty
+/// page is received. Many pages can be requested concurrently, as long as the bucket of the server
+/// is expected to have enough free capacity for all of them, but the entries are always returned
+/// in order. When the bucket is too full, the stream waits instead of sending a request, and
+/// requests rejected with `429 Too Many Requests` are retried when the response has a
+/// `Retry-After` header.
+///
+/// When a [`PageCache`] is used, fresh cached pages are returned without any request, therefore
+/// the bucket is only used for the pages missing from the cache or to be revalidated.
+///
+/// `T` is usually a [`Projection`] of the fields in the query, or [`RawEntry`] when all the fields
+/// are requested.
+///
+/// [`Projection`]: crate::database::Projection
+/// [`RawEntry`]: crate::database::RawEntry
+pub struct EntryStream<T> {
+    client: reqwest::Client,
+    url: Url,
+
+    /// The query, used as a template for the query of each page.
+    query: ServerQuery,
+
+    /// The position of the first entry of the next page to request.
+    position: usize,
+
+    /// The position of the next entry to return.
+    next_entry: usize,
+
+    /// The maximum page size, when the page size is chosen adaptively.
+    max_page_size: Option<u16>,
+    bucket: Option<LeakyBucket>,
+    cache: Option<PageCache>,
+    max_in_flight: usize,
+
+    /// The model used to estimate the cost of the requests.
+    cost_model: Box<dyn CostModel + Send + Sync>,
+    entries: vec::IntoIter<T>,
+
+    /// The pages requested and not returned yet, in order.
+    pages: VecDeque<Page<T>>,
+
+    /// Wakes the stream up when the bucket is expected to have enough free capacity for the next
+    /// request.
+    sleep: Option<Pin<Box<Sleep>>>,
+
+    /// Whether the last page has been found or an error occurred, therefore no more pages must
+    /// be requested.
+    finished: bool,
+}
+
+/// A page requested by [`EntryStream`].
+struct Page<T> {
+    query: ServerQuery,
+
+    /// The URL of the page, query included, used as the key of the cache.
+    key: String,
+
+    /// The cost expected for the request.
+    cost: u16,
+    state: PageState<T>,
+}
+
+enum PageState<T> {
+    /// The request must be sent again.
+    Retry,
+    Fetching(Pin<Box<dyn Future<Output = reqwest::Result<RawResponse>> + Send>>),
+    Received(Result<Vec<T>, Error>),
+}
+
+/// The parts of a response needed by [`EntryStream`].
+struct RawResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl<T> EntryStream<T> {
+    /// Creates a stream of the entries returned by the server at `url` for the given query.
+    #[must_use]
+    pub fn new(client: reqwest::Client, url: Url, query: ServerQuery) -> Self {
+        let position = query
+            .page
+            .unwrap_or(0)
+            .saturating_mul(page_size(&query).into());
+
+        Self {
+            client,
+            url,
+            query,
+            position,
+            next_entry: position,
+            max_page_size: None,
+            bucket: None,
+            cache: None,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            cost_model: Box::new(WeightedCost::default()),
+            entries: Vec::new().into_iter(),
+            pages: VecDeque::new(),
+            sleep: None,
+            finished: false,
+        }
+    }
+
+    /// Stores the received pages in the given cache, and revalidates the pages already in it
+    /// instead of downloading them again.
+    #[must_use]
+    pub fn with_cache(mut self, cache: PageCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Sets the maximum number of pages requested concurrently.
+    ///
+    /// The actual number depends on the free capacity of the bucket. Pages received ahead of the
+    /// returned entries count as well, therefore this also limits the memory used for them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_in_flight` is zero.
+    #[must_use]
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        assert!(max_in_flight > 0, "at least one page must be requested");
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Chooses the size of each page from the state of the bucket, up to `max_page_size`.
+    ///
+    /// Large pages need less requests, but they could not fit in the bucket at all: the largest
+    /// page that can be requested immediately is chosen, as long as it is not smaller than the
+    /// entries leaking from the bucket in a second. The page size of the query is used until the
+    /// state of the bucket is known.
+    ///
+    /// The position in the stream is kept across size changes, therefore no entry is skipped or
+    /// returned twice. Keep in mind that cached pages are only reused for the same page size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_page_size` is zero.
+    #[must_use]
+    pub fn with_adaptive_page_size(mut self, max_page_size: u16) -> Self {
+        assert!(max_page_size > 0, "pages must contain at least one entry");
+        self.max_page_size = Some(max_page_size);
+        self
+    }
+
+    /// Estimates the cost of the requests with the given model, which should be the one of the
+    /// server (see [`fetch_cost_model`]).
+    ///
+    /// If the model underestimates the costs, some requests are rejected and retried.
+    #[must_use]
+    pub fn with_cost_model(mut self, cost_model: impl CostModel + Send + Sync + 'static) -> Self {
+        self.cost_model = Box::new(cost_model);
+        self
+    }
+
+    /// Returns the local copy of the bucket of the server, available after the first response.
+    ///
+    /// The points include the expected cost of the requests in flight.
+    pub fn bucket(&self) -> Option<&LeakyBucket> {
+        self.bucket.as_ref()
+    }
+
+    /// Returns the query of the next page to request.
+    ///
+    /// The page size is reduced, if needed, so that the position of the next entry is the start
+    /// of a page.
+    fn next_page_query(&self) -> ServerQuery {
+        let page_size = (1..=self.next_page_size())
+            .rev()
+            .find(|&page_size| self.position.is_multiple_of(usize::from(page_size)))
+            .unwrap_or(1);
+
+        ServerQuery {
+            page: Some(self.position / usize::from(page_size)),
+            page_size: Some(page_size),
+            ..self.query.clone()
+        }
+    }
+
+    /// Returns the size of the next page to request, before aligning it to the position.
+    fn next_page_size(&self) -> u16 {
+        let page_size = page_size(&self.query);
+        let Some(max_page_size) = self.max_page_size else {
+            return page_size;
+        };
+        let Some(bucket) = &self.bucket else {
+            return page_size.min(max_page_size);
+        };
+
+        // The cost grows with the page size, therefore a binary search can be used.
+        let largest_page_size = |points: u16| {
+            let (mut low, mut high) = (0, max_page_size);
+            while low < high {
+                let middle = low + (high - low).div_ceil(2);
+                let query = ServerQuery {
+                    page_size: Some(middle),
+                    ..self.query.clone()
+                };
+                if self.cost_model.query_cost(&query) <= points {
+                    low = middle;
+                } else {
+                    high = middle - 1;
+                }
+            }
+            low
+        };
+
+        let limit = largest_page_size(bucket.capacity()).max(1);
+        let min = largest_page_size(bucket.leak_per_second().into()).clamp(1, limit);
+        largest_page_size(bucket.available()).clamp(min, limit)
+    }
+
+    /// Returns the expected cost of the request for a page.
+    ///
+    /// If the page is cached, it is likely to be unchanged, therefore the cost of a
+    /// `304 Not Modified` response is expected. If the page changed in the meantime, the request
+    /// could be rejected and it is retried.
+    fn expected_cost(&self, query: &ServerQuery, key: &str) -> u16 {
+        let is_cached = self
+            .cache
+            .as_ref()
+            .is_some_and(|cache| cache.get(key).is_some());
+        let cost = self.cost_model.query_cost(query);
+        if is_cached {
+            self.cost_model.not_modified_cost(cost)
+        } else {
+            cost
+        }
+    }
+
+    /// Checks that the bucket can contain the cost of a page, otherwise its request would be
+    /// rejected forever.
+    ///
+    /// The server reserves the cost of the whole page even if it turns out to be not modified.
+    fn check_capacity(&self, query: &ServerQuery) -> Result<(), Error> {
+        let Some(bucket) = &self.bucket else {
+            return Ok(());
+        };
+
+        let cost = self.cost_model.query_cost(query);
+        let capacity = bucket.capacity();
+        if cost > capacity {
+            Err(Error::CostExceedsCapacity { cost, capacity })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks whether a request with the given cost can be sent now.
+    ///
+    /// Otherwise the stream is set up to be woken up when it is expected to be possible.
+    fn can_afford(&mut self, cost: u16) -> bool {
+        let Some(bucket) = &self.bucket else {
+            // Until the state of the bucket is known, a single request at a time is sent.
+            return self
+                .pages
+                .iter()
+                .all(|page| matches!(page.state, PageState::Fetching(_)).not());
+        };
+
+        let wait_time = bucket.wait_time_to_use(cost);
+        if wait_time.is_zero() {
+            self.sleep = None;
+            true
+        } else {
+            self.sleep = Some(Box::pin(sleep(wait_time)));
+            false
+        }
+    }
+
+    /// Returns the total expected cost of the requests in flight.
+    fn in_flight_cost(&self) -> u16 {
+        self.pages
+            .iter()
+            .filter(|page| matches!(page.state, PageState::Fetching(_)))
+            .fold(0, |cost, page| cost.saturating_add(page.cost))
+    }
+
+    /// Returns the URL of the page for the given query.
+    fn page_key(&self, query: &ServerQuery) -> String {
+        let mut url = self.url.clone();
+        url.set_query(Some(
+            &serde_qs::to_string(query).expect("all fields should be valid"),
+        ));
+        url.into()
+    }
+
+    fn fetch(&self, key: &str) -> PageState<T> {
+        let mut request = self.client.get(key);
+        if let Some(page) = self.cache.as_ref().and_then(|cache| cache.get(key)) {
+            request = request.header(IF_NONE_MATCH, page.etag);
+        }
+
+        PageState::Fetching(Box::pin(async move {
+            let response = request.send().await?;
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?.to_vec();
+            Ok(RawResponse {
+                status,
+                headers,
+                body,
+            })
+        }))
+    }
+
+    /// Stops requesting pages and drops the ones not returned yet.
+    fn finish(&mut self) {
+        self.finished = true;
+        self.pages.clear();
+        self.sleep = None;
+    }
+}
+
+impl<T: DeserializeOwned> EntryStream<T> {
+    /// Requests as many pages as the bucket allows, up to the maximum in flight.
+    ///
+    /// Pages to be retried come first. Returns whether any page has been requested or taken from
+    /// the cache.
+    fn request_pages(&mut self) -> bool {
+        let mut progress = false;
+        loop {
+            let retry_index = self
+                .pages
+                .iter()
+                .position(|page| matches!(page.state, PageState::Retry));
+
+            let (query, key) = if let Some(index) = retry_index {
+                let page = &self.pages[index];
+                (page.query.clone(), page.key.clone())
+            } else {
+                if self.finished || self.pages.len() >= self.max_in_flight {
+                    break;
+                }
+
+                let query = self.next_page_query();
+                let key = self.page_key(&query);
+                if let Some(entries) = self.fresh_entries(&key) {
+                    self.push_page(Page {
+                        query,
+                        key,
+                        cost: 0,
+                        state: PageState::Received(entries),
+                    });
+                    progress = true;
+                    continue;
+                }
+                (query, key)
+            };
+
+            if let Err(error) = self.check_capacity(&query) {
+                let page = Page {
+                    query,
+                    key,
+                    cost: 0,
+                    state: PageState::Received(Err(error)),
+                };
+                match retry_index {
+                    Some(index) => self.pages[index] = page,
+                    None => self.push_page(page),
+                }
+                self.finished = true;
+                progress = true;
+                break;
+            }
+
+            let cost = self.expected_cost(&query, &key);
+            if self.can_afford(cost).not() {
+                break;
+            }
+
+            let state = self.fetch(&key);
+            if let Some(bucket) = &self.bucket {
+                bucket.saturating_add(cost);
+            }
+
+            let page = Page {
+                query,
+                key,
+                cost,
+                state,
+            };
+            match retry_index {
+                Some(index) => self.pages[index] = page,
+                None => self.push_page(page),
+            }
+            progress = true;
+        }
+
+        progress
+    }
+
+    /// Adds a new page at the end of the queue, moving to the next one.
+    fn push_page(&mut self, page: Page<T>) {
+        if matches!(&page.state, PageState::Received(Ok(entries)) if entries.is_empty()) {
+            self.finished = true;
+        }
+
+        self.position += usize::from(page_size(&page.query));
+        self.pages.push_back(page);
+    }
+
+    /// Polls the requests in flight.
+    ///
+    /// Returns whether any request has completed.
+    fn poll_pages(&mut self, cx: &mut Context<'_>) -> bool {
+        let mut progress = false;
+        for index in 0..self.pages.len() {
+            let PageState::Fetching(response) = &mut self.pages[index].state else {
+                continue;
+            };
+            let Poll::Ready(response) = response.as_mut().poll(cx) else {
+                continue;
+            };
+
+            // The request is not in flight anymore.
+            self.pages[index].state = PageState::Retry;
+            let key = self.pages[index].key.clone();
+
+            self.pages[index].state = match response
+                .map_err(Error::Request)
+                .and_then(|response| self.handle_response(key, response))
+            {
+                Ok(None) => PageState::Retry,
+                Ok(Some(entries)) => {
+                    if entries.is_empty() {
+                        self.finished = true;
+                    }
+                    PageState::Received(Ok(entries))
+                }
+                Err(error) => {
+                    self.finished = true;
+                    PageState::Received(Err(error))
+                }
+            };
+            progress = true;
+        }
+
+        progress
+    }
+
+    /// Handles the response for a page.
+    ///
+    /// Returns `None` if the request must be retried.
+    fn handle_response(
+        &mut self,
+        key: String,
+        response: RawResponse,
+    ) -> Result<Option<Vec<T>>, Error> {
+        let RawResponse {
+            status,
+            headers,
+            body,
+        } = response;
+
+        let has_bucket = if let Ok(bucket) = LeakyBucket::try_from(&headers) {
+            // The server could have already handled some of the requests in flight, but it is not
+            // possible to know which ones: counting them again is the safe choice.
+            bucket.saturating_add(self.in_flight_cost());
+            self.bucket = Some(bucket);
+            true
+        } else {
+            // The points in the bucket headers already take the refund into account, otherwise the
+            // local bucket gives back the points reserved in excess.
+            let refund = headers
+                .get(BUCKET_REFUND_HEADER)
+                .and_then(|refund| refund.to_str().ok()?.parse().ok());
+            if let (Some(bucket), Some(refund)) = (&self.bucket, refund) {
+                bucket.refund(refund);
+            }
+            false
+        };
+
+        match status {
+            StatusCode::OK => {
+                let entries = serde_json::from_slice(&body).map_err(Error::InvalidBody)?;
+                let etag = headers.get(ETAG).and_then(|etag| etag.to_str().ok());
+
+                // A valid JSON body is always valid UTF-8.
+                if let (Some(cache), Some(etag), Ok(body)) =
+                    (&self.cache, etag, String::from_utf8(body))
+                {
+                    cache.insert(
+                        key,
+                        CachedPage {
+                            etag: etag.to_owned(),
+                            body,
+                            stored_at: SystemTime::now(),
+                        },
+                    );
+                }
+                Ok(Some(entries))
+            }
+            StatusCode::NOT_MODIFIED => {
+                let cache = self.cache.as_ref().ok_or(Error::MissingCachedPage)?;
+                let page = cache.get(&key).ok_or(Error::MissingCachedPage)?;
+                cache.revalidate(&key);
+                serde_json::from_str(&page.body)
+                    .map(Some)
+                    .map_err(Error::InvalidBody)
+            }
+
+            // Without the state of the bucket the stream would retry immediately, and without
+            // `Retry-After` the server will never be able to perform the request.
+            StatusCode::TOO_MANY_REQUESTS if has_bucket && headers.contains_key(RETRY_AFTER) => {
+                Ok(None)
+            }
+            status => Err(Error::Status(status)),
+        }
+    }
+
+    /// Returns the entries of a page from the cache, if it does not need to be revalidated.
+    fn fresh_entries(&self, key: &str) -> Option<Result<Vec<T>, Error>> {
+        let page = self.cache.as_ref()?.get_fresh(key)?;
+        Some(serde_json::from_str(&page.body).map_err(Error::InvalidBody))
+    }
+}
+
+// The entries are never pinned.
+impl<T> Unpin for EntryStream<T> {}
+
+impl<T: DeserializeOwned> Stream for EntryStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            // Pages are requested and polled even when entries are available, so that the next
+            // ones are fetched in the meantime.
+            let requested = this.request_pages();
+            let received = this.poll_pages(cx);
+
+            if let Some(entry) = this.entries.next() {
+                this.next_entry += 1;
+                return Poll::Ready(Some(Ok(entry)));
+            }
+
+            if matches!(
+                this.pages.front(),
+                Some(Page {
+                    state: PageState::Received(_),
+                    ..
+                })
+            ) {
+                let Some(Page {
+                    state: PageState::Received(entries),
+                    ..
+                }) = this.pages.pop_front()
+                else {
+                    unreachable!("the first page has been received");
+                };
+
+                match entries {
+                    Ok(entries) if entries.is_empty() => {
+                        this.finish();
+                        return Poll::Ready(None);
+                    }
+                    Ok(entries) => this.entries = entries.into_iter(),
+                    Err(error) => {
+                        this.finish();
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                }
+                continue;
+            }
+
+            if this.finished && this.pages.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            if requested || received {
+                continue;
+            }
+
+            match &mut this.sleep {
+                Some(sleep) => {
+                    ready!(sleep.as_mut().poll(cx));
+                    this.sleep = None;
+                }
+                None => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Retrieves the cost model advertised by the server at the given base URL.
+///
+/// The server advertises the model of the caller, scaled by the multiplier of its tier: the API
+/// key, if any, must be among the default headers of `client`. The model can be passed to
+/// [`EntryStream::with_cost_model`].
+///
+/// # Errors
+///
+/// Returns an error if the request fails, or the server does not answer with a [`ScaledCost`] of a
+/// [`WeightedCost`].
+///
+/// # Panics
+///
+/// Panics if `url` cannot be a base URL.
+pub async fn fetch_cost_model(client: &reqwest::Client, url: &Url) -> Result<ScaledCost, Error> {
+    let url = url
+        .join(COST_MODEL_PATH)
+        .expect("the URL of the server should be a base URL");
+    let response = client.get(url).send().await.map_err(Error::Request)?;
+    let status = response.status();
+    if status.is_success().not() {
+        return Err(Error::Status(status));
+    }
+
+    let body = response.bytes().await.map_err(Error::Request)?;
+    serde_json::from_slice(&body).map_err(Error::InvalidBody)
+}
+
+/// Returns the page size of the query, which is never zero.
+fn page_size(query: &ServerQuery) -> u16 {
+    query.page_size.unwrap_or(DEFAULT_PAGE_SIZE).max(1)
+}
+
+/// An error returned by [`EntryStream`].
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be performed.
+    Request(reqwest::Error),
+
+    /// The server answered with an unexpected status.
+    Status(StatusCode),
+
+    /// The body of the response does not contain the expected entries.
+    InvalidBody(serde_json::Error),
+
+    /// The server answered `304 Not Modified`, but the page is not in the cache.
+    MissingCachedPage,
+
+    /// The cost of a page exceeds the capacity of the bucket, therefore the server would never
+    /// accept its request.
+    ///
+    /// Smaller pages or less fields are needed, see [`EntryStream::with_adaptive_page_size`].
+    CostExceedsCapacity { cost: u16, capacity: u16 },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Request(error) => write!(f, "request failed: {error}"),
+            Error::Status(status) => write!(f, "unexpected status {status}"),
+            Error::InvalidBody(error) => write!(f, "invalid response body: {error}"),
+            Error::MissingCachedPage => {
+                f.write_str("page not modified, but missing from the cache")
+            }
+            Error::CostExceedsCapacity { cost, capacity } => write!(
+                f,
+                "a page costs {cost} points, more than the capacity of the bucket ({capacity})"
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Request(error) => Some(error),
+            Error::InvalidBody(error) => Some(error),
+            Error::Status(_) | Error::MissingCachedPage | Error::CostExceedsCapacity { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        ops::Range,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use axum::{
+        extract::Query,
+        http::{self, HeaderValue},
+        response::IntoResponse,
+        routing::get,
+        Extension, Router,
+    };
+    use futures_util::StreamExt;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::projection, BUCKET_CAPACITY_HEADER, BUCKET_LEAK_PER_SECOND_HEADER,
+        BUCKET_POINTS_HEADER,
+    };
+
+    projection! {
+        #[derive(Debug, PartialEq)]
+        struct Name {
+            Name,
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct PageQuery {
+        page: Option<usize>,
+        page_size: Option<u16>,
+    }
+
+    /// A fake server, with an always empty bucket.
+    #[derive(Debug, Default)]
+    struct TestServer {
+        /// The number of entries, each one named after its position.
+        entries: usize,
+        capacity: u16,
+        /// Rejects every request with `429 Too Many Requests`, without `Retry-After`.
+        reject: bool,
+        requests: AtomicUsize,
+        not_modified: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        max_page_size: AtomicUsize,
+    }
+
+    /// Serves the pages, answering `304 Not Modified` when possible.
+    async fn serve(
+        Query(query): Query<PageQuery>,
+        headers: http::HeaderMap,
+        Extension(server): Extension<Arc<TestServer>>,
+    ) -> impl IntoResponse {
+        server.requests.fetch_add(1, Ordering::Relaxed);
+        let in_flight = server.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        server.max_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        server.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        let page_size = usize::from(query.page_size.unwrap_or(DEFAULT_PAGE_SIZE));
+        server.max_page_size.fetch_max(page_size, Ordering::Relaxed);
+        let start = query.page.unwrap_or(0) * page_size;
+        let end = (start + page_size).min(server.entries);
+
+        let etag = format!("\"{start}-{end}\"");
+        let body: Vec<_> = (start..end)
+            .map(|position| json!({ "name": position.to_string() }))
+            .collect();
+
+        let mut response_headers = http::HeaderMap::new();
+        response_headers.insert(BUCKET_POINTS_HEADER, HeaderValue::from(0));
+        response_headers.insert(BUCKET_CAPACITY_HEADER, HeaderValue::from(server.capacity));
+        response_headers.insert(BUCKET_LEAK_PER_SECOND_HEADER, HeaderValue::from(10));
+        response_headers.insert(http::header::ETAG, etag.parse().unwrap());
+
+        if server.reject {
+            (http::StatusCode::TOO_MANY_REQUESTS, response_headers).into_response()
+        } else if headers.get(http::header::IF_NONE_MATCH) == Some(&etag.parse().unwrap()) {
+            server.not_modified.fetch_add(1, Ordering::Relaxed);
+            (http::StatusCode::NOT_MODIFIED, response_headers).into_response()
+        } else {
+            (response_headers, axum::Json(body)).into_response()
+        }
+    }
+
+    fn spawn_server(server: &Arc<TestServer>) -> Url {
+        let app = Router::new()
+            .route("/", get(serve))
+            .layer(Extension(Arc::clone(server)));
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(app.into_make_service());
+        let url = Url::parse(&format!("http://{}/", server.local_addr())).unwrap();
+        tokio::spawn(server);
+        url
+    }
+
+    /// Creates a stream of pages containing a single entry.
+    fn names_stream(url: &Url) -> EntryStream<Name> {
+        let query = ServerQuery {
+            page_size: Some(1),
+            ..ServerQuery::for_projection::<Name>()
+        };
+        EntryStream::new(reqwest::Client::new(), url.clone(), query)
+    }
+
+    fn names(range: Range<usize>) -> Vec<String> {
+        range.map(|position| position.to_string()).collect()
+    }
+
+    async fn collect_names(stream: EntryStream<Name>) -> Vec<String> {
+        stream
+            .map(|entry| entry.unwrap().name)
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[tokio::test]
+    async fn cached_pages() {
+        let server = Arc::new(TestServer {
+            entries: 2,
+            capacity: 100,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+
+        let cache = PageCache::new();
+        let collect = || {
+            collect_names(
+                names_stream(&url)
+                    .with_cache(cache.clone())
+                    .with_max_in_flight(1),
+            )
+        };
+
+        assert_eq!(collect().await, names(0..2));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(server.not_modified.load(Ordering::Relaxed), 0);
+
+        assert_eq!(collect().await, names(0..2));
+        assert_eq!(server.requests.load(Ordering::Relaxed), 6);
+        assert_eq!(server.not_modified.load(Ordering::Relaxed), 3);
+
+        // The settings are shared by the clones. Fresh pages do not need any request.
+        let _cache = cache.clone().with_ttl(Duration::from_mins(1));
+        assert_eq!(collect().await, names(0..2));
+        assert_eq!(server.requests.load(Ordering::Relaxed), 6);
+    }
+
+    #[tokio::test]
+    async fn cache_shared_by_servers() {
+        let cache = PageCache::new().with_ttl(Duration::from_mins(1));
+        for entries in [2, 4] {
+            let server = Arc::new(TestServer {
+                entries,
+                capacity: 100,
+                ..TestServer::default()
+            });
+            let url = spawn_server(&server);
+            let stream = names_stream(&url)
+                .with_cache(cache.clone())
+                .with_max_in_flight(1);
+            assert_eq!(collect_names(stream).await, names(0..entries));
+        }
+        assert_eq!(cache.len(), 8);
+    }
+
+    #[tokio::test]
+    async fn concurrent_pages() {
+        let server = Arc::new(TestServer {
+            entries: 8,
+            capacity: 1000,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+        assert_eq!(collect_names(names_stream(&url)).await, names(0..8));
+        assert_eq!(
+            server.max_in_flight.load(Ordering::Relaxed),
+            DEFAULT_MAX_IN_FLIGHT
+        );
+
+        // Each page costs a single point, which is the capacity of the bucket.
+        let server = Arc::new(TestServer {
+            entries: 8,
+            capacity: 1,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+        assert_eq!(collect_names(names_stream(&url)).await, names(0..8));
+        assert_eq!(server.max_in_flight.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn adaptive_page_size() {
+        let server = Arc::new(TestServer {
+            entries: 100,
+            capacity: 30,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+
+        // The stream starts from the third entry, the page size grows as soon as the position
+        // allows it.
+        let query = ServerQuery {
+            page: Some(3),
+            page_size: Some(1),
+            ..ServerQuery::for_projection::<Name>()
+        };
+        let stream = EntryStream::<Name>::new(reqwest::Client::new(), url.clone(), query)
+            .with_adaptive_page_size(16);
+        assert_eq!(collect_names(stream).await, names(3..100));
+        assert_eq!(server.max_page_size.load(Ordering::Relaxed), 16);
+
+        // The bucket cannot contain pages larger than its capacity.
+        let server = Arc::new(TestServer {
+            entries: 40,
+            capacity: 8,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+        let stream = names_stream(&url).with_adaptive_page_size(16);
+        assert_eq!(collect_names(stream).await, names(0..40));
+        assert_eq!(server.max_page_size.load(Ordering::Relaxed), 8);
+    }
+
+    #[tokio::test]
+    async fn cost_exceeds_capacity() {
+        let server = Arc::new(TestServer {
+            entries: 20,
+            capacity: 3,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+
+        // The first page is requested before knowing the bucket.
+        let query = ServerQuery {
+            page_size: Some(5),
+            ..ServerQuery::for_projection::<Name>()
+        };
+        let results: Vec<_> = EntryStream::<Name>::new(reqwest::Client::new(), url, query)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 6);
+        assert!(results[..5].iter().all(Result::is_ok));
+        assert!(matches!(
+            results[5],
+            Err(Error::CostExceedsCapacity {
+                cost: 5,
+                capacity: 3
+            })
+        ));
+        assert_eq!(server.requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn rejected_without_retry_after() {
+        let server = Arc::new(TestServer {
+            entries: 4,
+            capacity: 100,
+            reject: true,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+
+        let results: Vec<_> = names_stream(&url).with_max_in_flight(1).collect().await;
+        assert!(matches!(
+            results[..],
+            [Err(Error::Status(http::StatusCode::TOO_MANY_REQUESTS))]
+        ));
+        assert_eq!(server.requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn resume_from_checkpoint() {
+        let server = Arc::new(TestServer {
+            entries: 10,
+            capacity: 100,
+            ..TestServer::default()
+        });
+        let url = spawn_server(&server);
+
+        let mut stream = names_stream(&url);
+        let first: Vec<_> = stream
+            .by_ref()
+            .take(4)
+            .map(|entry| entry.unwrap().name)
+            .collect()
+            .await;
+        assert_eq!(first, names(0..4));
+
+        let checkpoint = stream.checkpoint();
+        assert_eq!(checkpoint.position, 4);
+        assert_eq!(checkpoint.bucket.unwrap().capacity, 100);
+
+        // The pages already requested ahead are requested again.
+        let checkpoint = serde_json::to_string(&checkpoint).unwrap();
+        let checkpoint = serde_json::from_str(&checkpoint).unwrap();
+        let stream = EntryStream::resume(reqwest::Client::new(), url, checkpoint);
+        assert_eq!(collect_names(stream).await, names(4..10));
+    }
+}
@@ -0,0 +1,414 @@
+#![warn(clippy::pedantic)]
+
+//! A cache of the pages received from the server, optionally persisted on disk.
+
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hasher,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::fnv::Fnv1a;
+
+/// A cache of the pages received from the server, keyed by their URL.
+///
+/// Pages stored less than a _time to live_ ago are used without contacting the server, older
+/// pages are revalidated with a conditional request. Without a time to live, pages are always
+/// revalidated. When the total size of the pages exceeds the limit, the pages stored or
+/// revalidated least recently are evicted.
+///
+/// A cache created with [`PageCache::open`] is persisted in a directory, one file per page,
+/// therefore it survives across runs. Failing to write the files is not fatal: the page is still
+/// kept in memory.
+///
+/// Cloning the cache is cheap, and all the clones share the same pages. In this way the same
+/// cache can be used by many [`EntryStream`]s, for instance to poll the same query again.
+///
+/// [`EntryStream`]: super::EntryStream
+#[derive(Clone, Debug, Default)]
+pub struct PageCache {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    pages: HashMap<String, CachedPage>,
+
+    /// The total size of the bodies of the pages, in bytes.
+    size: usize,
+    dir: Option<PathBuf>,
+    ttl: Option<Duration>,
+    max_size: Option<usize>,
+}
+
+/// A page stored in a [`PageCache`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CachedPage {
+    /// The `ETag` of the response, quotes included.
+    pub etag: String,
+
+    /// The JSON body of the response.
+    pub body: String,
+
+    /// When the page was received or last revalidated.
+    pub stored_at: SystemTime,
+}
+
+/// The content of the file of a page.
+#[derive(Serialize, Deserialize)]
+struct PageFile {
+    key: String,
+    etag: String,
+    body: String,
+
+    /// Seconds since the Unix epoch.
+    stored_at: u64,
+}
+
+impl PageCache {
+    /// Creates an empty in-memory cache.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the cache persisted in the given directory, creating it if missing.
+    ///
+    /// Files that cannot be read are skipped. If many files contain the same page, only the one
+    /// stored most recently is kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or listed.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut inner = Inner {
+            dir: Some(dir.clone()),
+            ..Inner::default()
+        };
+        let mut paths = HashMap::new();
+        for file in fs::read_dir(&dir)? {
+            let path = file?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let page_file = fs::read(&path).and_then(|content| {
+                serde_json::from_slice::<PageFile>(&content).map_err(io::Error::from)
+            });
+            match page_file {
+                Ok(page_file) => {
+                    let page = CachedPage {
+                        etag: page_file.etag,
+                        body: page_file.body,
+                        stored_at: SystemTime::UNIX_EPOCH
+                            + Duration::from_secs(page_file.stored_at),
+                    };
+                    let is_newer = inner
+                        .pages
+                        .get(&page_file.key)
+                        .is_none_or(|other| page.stored_at > other.stored_at);
+                    let stale_path = if is_newer {
+                        inner.size += page.body.len();
+                        if let Some(other) = inner.pages.insert(page_file.key.clone(), page) {
+                            inner.size -= other.body.len();
+                        }
+                        paths.insert(page_file.key, path)
+                    } else {
+                        Some(path)
+                    };
+
+                    if let Some(stale_path) = stale_path {
+                        if let Err(error) = fs::remove_file(&stale_path) {
+                            warn!(
+                                "unable to remove cached page {}: {error}",
+                                stale_path.display()
+                            );
+                        }
+                    }
+                }
+                Err(error) => warn!("skipping cached page {}: {error}", path.display()),
+            }
+        }
+
+        // Files named by another hash of the key would not be found when the page is replaced.
+        for (key, path) in paths {
+            let Some(page_path) = inner.page_path(&key) else {
+                continue;
+            };
+            if path != page_path {
+                if let Err(error) = fs::rename(&path, &page_path) {
+                    warn!("unable to rename cached page {}: {error}", path.display());
+                }
+            }
+        }
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Sets the time during which a page is used without revalidating it.
+    #[must_use]
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        self.lock().ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the maximum total size of the bodies of the pages, in bytes.
+    ///
+    /// Pages are evicted immediately if the limit is already exceeded.
+    #[must_use]
+    pub fn with_max_size(self, max_size: usize) -> Self {
+        {
+            let mut inner = self.lock();
+            inner.max_size = Some(max_size);
+            inner.evict();
+        }
+        self
+    }
+
+    /// Returns the page cached for the given key, if any.
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<CachedPage> {
+        self.lock().pages.get(key).cloned()
+    }
+
+    /// Returns the page cached for the given key if it does not need to be revalidated.
+    #[must_use]
+    pub fn get_fresh(&self, key: &str) -> Option<CachedPage> {
+        let inner = self.lock();
+        let ttl = inner.ttl?;
+        inner
+            .pages
+            .get(key)
+            .filter(|page| page.stored_at.elapsed().is_ok_and(|age| age < ttl))
+            .cloned()
+    }
+
+    /// Stores the page for the given key, replacing the previous one.
+    ///
+    /// A page larger than the maximum size is not stored.
+    pub fn insert(&self, key: String, page: CachedPage) {
+        let mut inner = self.lock();
+        if inner
+            .max_size
+            .is_some_and(|max_size| page.body.len() > max_size)
+        {
+            inner.remove(&key);
+            return;
+        }
+
+        inner.persist(&key, &page);
+        inner.size += page.body.len();
+        if let Some(old_page) = inner.pages.insert(key, page) {
+            inner.size -= old_page.body.len();
+        }
+        inner.evict();
+    }
+
+    /// Marks the page for the given key as revalidated now, if present.
+    pub fn revalidate(&self, key: &str) {
+        let mut inner = self.lock();
+        let Some(page) = inner.pages.get_mut(key) else {
+            return;
+        };
+
+        page.stored_at = SystemTime::now();
+        let page = page.clone();
+        inner.persist(key, &page);
+    }
+
+    /// Returns the number of cached pages.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().pages.len()
+    }
+
+    /// Returns whether the cache is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.lock().pages.is_empty()
+    }
+
+    /// Returns the total size of the bodies of the pages, in bytes.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.lock().size
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The cache is always left in a consistent state, even by a panicking thread.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Inner {
+    /// Removes the pages stored least recently until the size is within the limit.
+    fn evict(&mut self) {
+        let Some(max_size) = self.max_size else {
+            return;
+        };
+
+        while self.size > max_size {
+            let Some(oldest) = self
+                .pages
+                .iter()
+                .min_by_key(|(_, page)| page.stored_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(page) = self.pages.remove(key) else {
+            return;
+        };
+        self.size -= page.body.len();
+
+        if let Some(path) = self.page_path(key) {
+            if let Err(error) = fs::remove_file(&path) {
+                warn!("unable to remove cached page {}: {error}", path.display());
+            }
+        }
+    }
+
+    /// Writes the page to its file, if the cache is persisted.
+    fn persist(&self, key: &str, page: &CachedPage) {
+        let Some(path) = self.page_path(key) else {
+            return;
+        };
+
+        let page_file = PageFile {
+            key: key.to_owned(),
+            etag: page.etag.clone(),
+            body: page.body.clone(),
+            stored_at: page
+                .stored_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+        };
+        if let Err(error) = write_atomically(&path, &page_file) {
+            warn!("unable to persist cached page {}: {error}", path.display());
+        }
+    }
+
+    /// Returns the path of the file of a page, if the cache is persisted.
+    fn page_path(&self, key: &str) -> Option<PathBuf> {
+        let mut hasher = Fnv1a::default();
+        hasher.write(key.as_bytes());
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{:016x}.json", hasher.finish())))
+    }
+}
+
+/// Writes the file through a temporary one, so that a crash never leaves a partial file.
+fn write_atomically(path: &Path, page_file: &PageFile) -> io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, serde_json::to_vec(page_file)?)?;
+    fs::rename(temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn page(body: &str, stored_at: SystemTime) -> CachedPage {
+        CachedPage {
+            etag: format!("\"{body}\""),
+            body: body.to_owned(),
+            stored_at,
+        }
+    }
+
+    #[test]
+    fn ttl_and_eviction() {
+        let now = SystemTime::now();
+        let cache = PageCache::new()
+            .with_ttl(Duration::from_mins(1))
+            .with_max_size(10);
+
+        cache.insert(
+            "page=0".to_owned(),
+            page("[1,2]", now - Duration::from_mins(2)),
+        );
+        cache.insert("page=1".to_owned(), page("[3,4]", now));
+        assert!(cache.get("page=0").is_some());
+        assert!(cache.get_fresh("page=0").is_none());
+        assert!(cache.get_fresh("page=1").is_some());
+        assert_eq!(cache.size(), 10);
+
+        cache.insert("page=2".to_owned(), page("[5]", now));
+        assert!(cache.get("page=0").is_none());
+        assert_eq!(cache.size(), 8);
+
+        cache.insert("page=3".to_owned(), page("[6,7,8,9,10]", now));
+        assert!(cache.get("page=3").is_none());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = env::temp_dir().join(format!("page-cache-test-{}", process::id()));
+        let stored_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_666_000_000);
+
+        let cache = PageCache::open(&dir).unwrap();
+        cache.insert(
+            "page=0".to_owned(),
+            page("[1,2]", stored_at - Duration::from_secs(1)),
+        );
+        cache.insert("page=1".to_owned(), page("[3]", stored_at));
+
+        let reopened = PageCache::open(&dir).unwrap().with_max_size(3);
+        assert_eq!(reopened.get("page=1"), Some(page("[3]", stored_at)));
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(PageCache::open(&dir).unwrap().len(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn duplicated_pages() {
+        let dir = env::temp_dir().join(format!("page-cache-duplicates-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let page_file = |body: &str, stored_at: u64| PageFile {
+            key: "page=0".to_owned(),
+            etag: format!("\"{body}\""),
+            body: body.to_owned(),
+            stored_at,
+        };
+        write_atomically(&dir.join("old.json"), &page_file("[1,2]", 1)).unwrap();
+        write_atomically(&dir.join("new.json"), &page_file("[3]", 2)).unwrap();
+
+        let cache = PageCache::open(&dir).unwrap();
+        assert_eq!(
+            cache.get("page=0"),
+            Some(page("[3]", SystemTime::UNIX_EPOCH + Duration::from_secs(2)))
+        );
+        assert_eq!(cache.size(), 3);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The remaining file is the one replaced by a new page.
+        cache.insert("page=0".to_owned(), page("[4]", SystemTime::now()));
+        assert_eq!(
+            PageCache::open(&dir).unwrap().get("page=0").unwrap().body,
+            "[4]"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
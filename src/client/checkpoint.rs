@@ -0,0 +1,65 @@
+#![warn(clippy::pedantic)]
+
+//! Checkpoints of an [`EntryStream`], used to resume it later, even from another process.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::EntryStream;
+use crate::{database::ServerQuery, leaky_bucket::Snapshot, LeakyBucket};
+
+/// The state of an [`EntryStream`], which can be persisted in order to resume the stream exactly
+/// where it stopped.
+///
+/// The checkpoint only refers to the entries already returned: the pages requested ahead are
+/// requested again by the resumed stream.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The query of the stream.
+    ///
+    /// [`ServerQuery::page`] is ignored when resuming, `position` is used instead.
+    pub query: ServerQuery,
+
+    /// The position of the next entry to return.
+    pub position: usize,
+
+    /// The local copy of the bucket of the server, if known.
+    ///
+    /// The points include the expected cost of the requests in flight.
+    pub bucket: Option<Snapshot>,
+}
+
+impl<T> EntryStream<T> {
+    /// Resumes a stream from a checkpoint taken with [`EntryStream::checkpoint`].
+    ///
+    /// The cache, the maximum number of pages in flight and the adaptive page size are not part
+    /// of the checkpoint, therefore they must be set again.
+    #[must_use]
+    pub fn resume(client: reqwest::Client, url: Url, checkpoint: Checkpoint) -> Self {
+        let Checkpoint {
+            query,
+            position,
+            bucket,
+        } = checkpoint;
+
+        let mut stream = Self::new(client, url, query);
+        stream.position = position;
+        stream.next_entry = position;
+        stream.bucket = bucket.map(LeakyBucket::restore);
+        stream
+    }
+
+    /// Takes a checkpoint of the stream, which can be persisted and used to resume it with
+    /// [`EntryStream::resume`].
+    ///
+    /// A checkpoint can be taken at any time, for instance periodically while the entries are
+    /// processed. After the stream ends, the checkpoint points after the last entry returned.
+    #[must_use]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            query: self.query.clone(),
+            position: self.next_entry,
+            bucket: self.bucket.as_ref().map(LeakyBucket::snapshot),
+        }
+    }
+}
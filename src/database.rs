@@ -2,35 +2,74 @@
 
 //! Helper structures and functions to easily interact with the example database.
 
-use std::{collections::HashSet, ops::Not};
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    num::NonZeroU16,
+};
 
-use reqwest::{Method, Url};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use url::Url;
 
-/// A single entry of the database.
-#[derive(Debug, Serialize, Deserialize)]
+pub use cost::{ChargeBy, CostModel, ScaledCost, WeightedCost, COST_MODEL_PATH};
+pub use raw::{EntryError, FieldError, FieldErrorKind, RawEntry};
+// The tests use the macro even without the feature, through the development dependency.
+#[cfg(any(test, feature = "macros"))]
+pub use workshop_rustlab_2022_macros::projection;
+
+pub mod cost;
+#[cfg(test)]
+pub(crate) mod fixtures;
+pub mod geo_shape;
+pub mod raw;
+
+/// A single entry of the database, with typed fields.
+///
+/// The database and the server use the [`RawEntry`] representation, in which every attribute is
+/// a string. This type is (de)serialized through [`RawEntry`] as well, therefore the JSON format is
+/// the same, but values that cannot be parsed cause an [`EntryError`].
+///
+/// The conversion from and to [`RawEntry`] is lossless: a [`RawEntry`] that successfully converts
+/// to `Entry` is obtained back with the same values, although numbers and URLs are normalized.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawEntry", into = "RawEntry")]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Entry {
+    /// The identifier of the entry, assigned by the server when the entry is stored.
+    pub id: Option<EntryId>,
     pub geo_point_2d: GeoPoint2d,
     pub geo_shape: GeoShape,
     pub name: String,
     pub etichetta: String,
     pub notetesto: String,
     pub numeroantico: String,
-    pub numeromoderno: String,
-    pub link1: String,
-    pub link2: String,
-    pub link3: String,
-    pub piani: String,
+
+    /// The modern street number, if any.
+    pub numeromoderno: Option<u32>,
+    pub link1: Option<Url>,
+    pub link2: Option<Url>,
+    pub link3: Option<Url>,
+
+    /// The number of floors, if known.
+    pub piani: Option<u8>,
     pub arcate: String,
     pub architravate: String,
     pub architravate_con_colonne_di_legno: String,
     pub archivolti: String,
     pub modiglioni: String,
     pub mensoloni_architravati: String,
-    pub stalla_e: String,
-    pub fienile_i: String,
-    pub rimessa_e: String,
-    pub scuderia_e: String,
+
+    /// Whether the building has one or more stables.
+    pub stalla_e: bool,
+
+    /// Whether the building has one or more haylofts.
+    pub fienile_i: bool,
+
+    /// Whether the building has one or more sheds.
+    pub rimessa_e: bool,
+
+    /// Whether the building has one or more horse stables.
+    pub scuderia_e: bool,
     pub attivita_commerciali_produttive_1: String,
     pub attivita_commerciali_produttive_2: String,
     pub attivita_commerciali_produttive_3: String,
@@ -38,8 +77,25 @@ pub struct Entry {
     pub attivita_commerciali_produttive_5: String,
 }
 
+/// The stable identifier of an entry.
+///
+/// Entries of the base database keep the identifier stored in the database, if any, otherwise
+/// they are numbered following their order. New entries get increasing identifiers, which are never
+/// reused. The identifiers never change, regardless of filtering, sorting or edits: the server
+/// refuses to replay its write-ahead log over a different base database, which must store the
+/// identifiers of the existing entries instead.
+#[derive(Clone, Copy, Debug, Hash, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EntryId(pub u64);
+
+impl Display for EntryId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
 /// A geographic point with longitude and latitude.
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct GeoPoint2d {
     /// The longitude.
     pub lon: f64,
@@ -49,29 +105,12 @@ pub struct GeoPoint2d {
 }
 
 /// A geographic shape.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum GeoShape {
     Feature(geo_shape::Feature),
 }
 
-pub mod geo_shape {
-    //! The supported types of geographic shapes.
-
-    use super::{Deserialize, Serialize};
-
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    pub struct Feature {
-        pub geometry: FeatureGeometry,
-    }
-
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    #[serde(tag = "type")]
-    pub enum FeatureGeometry {
-        Polygon { coordinates: Vec<Vec<[f64; 3]>> },
-    }
-}
-
 /// The representation of the HTTP query supported by the server.
 ///
 /// This type is exposed in order to make both the server and eventual clients share the same kind
@@ -79,9 +118,13 @@ pub mod geo_shape {
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ServerQuery {
     /// The fields to include in the request. Keep in mind that you would need to create a
-    /// `CustomEntry` struct based on [`Entry`] containing only the specified `fields`.
-    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
-    pub fields: HashSet<ServerField>,
+    /// `CustomEntry` struct based on [`Entry`] containing only the specified `fields`: the
+    /// [`projection!`] macro can be used to generate it, and [`ServerQuery::for_projection`] to
+    /// create a query that always matches it.
+    ///
+    /// The fields are kept sorted, therefore the same query is always encoded in the same way.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub fields: BTreeSet<ServerField>,
 
     /// The page requested.
     ///
@@ -92,18 +135,34 @@ pub struct ServerQuery {
     ///
     /// If omitted, [`DEFAULT_PAGE_SIZE`] is implied.
     pub page_size: Option<u16>,
+
+    /// The tolerance, in meters, used to simplify the geometries of [`ServerField::GeoShape`].
+    ///
+    /// Simplified geometries are cheaper, see [`calc_query_cost`]. If omitted, the geometries are
+    /// sent unchanged. A tolerance of zero would not simplify anything, therefore it is rejected.
+    pub simplify: Option<NonZeroU16>,
 }
 
 impl ServerQuery {
+    /// Creates a query requesting exactly the fields of the given [`Projection`].
+    #[must_use]
+    pub fn for_projection<P: Projection>() -> Self {
+        Self {
+            fields: P::FIELDS.iter().copied().collect(),
+            ..Self::default()
+        }
+    }
+
     /// A simple helper to create a [`Request`] instance using the current fields.
     ///
     /// [`Request`]: `reqwest::Request`
+    #[cfg(feature = "client")]
     #[must_use]
     #[allow(clippy::missing_panics_doc)]
     pub fn create_request(&self, port: Option<u16>) -> reqwest::Request {
         let mut url = Url::parse("http://localhost").unwrap();
         url.set_port(port).unwrap();
-        let mut request = reqwest::Request::new(Method::GET, url);
+        let mut request = reqwest::Request::new(reqwest::Method::GET, url);
 
         request.url_mut().set_query(Some(
             &serde_qs::to_string(self).expect("all fields should be valid"),
@@ -116,9 +175,10 @@ impl ServerQuery {
 /// The possible fields for the query/response.
 ///
 /// All the variants have a direct relationship with a fields in [`Entry`].
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ServerField {
+    Id,
     GeoPoint2d,
     GeoShape,
     Name,
@@ -148,10 +208,42 @@ pub enum ServerField {
 }
 
 impl ServerField {
+    /// All the fields, in the order of [`Entry`].
+    pub const ALL: [Self; FIELDS_LEN as usize] = [
+        ServerField::Id,
+        ServerField::GeoPoint2d,
+        ServerField::GeoShape,
+        ServerField::Name,
+        ServerField::Etichetta,
+        ServerField::Notetesto,
+        ServerField::Numeroantico,
+        ServerField::Numeromoderno,
+        ServerField::Link1,
+        ServerField::Link2,
+        ServerField::Link3,
+        ServerField::Piani,
+        ServerField::Arcate,
+        ServerField::Architravate,
+        ServerField::ArchitravateConColonneDiLegno,
+        ServerField::Archivolti,
+        ServerField::Modiglioni,
+        ServerField::MensoloniArchitravati,
+        ServerField::StallaE,
+        ServerField::FienileI,
+        ServerField::RimessaE,
+        ServerField::ScuderiaE,
+        ServerField::AttivitaCommercialiProduttive1,
+        ServerField::AttivitaCommercialiProduttive2,
+        ServerField::AttivitaCommercialiProduttive3,
+        ServerField::AttivitaCommercialiProduttive4,
+        ServerField::AttivitaCommercialiProduttive5,
+    ];
+
     /// Return the string representation of the field.
     #[must_use]
     pub fn to_str(&self) -> &'static str {
         match self {
+            ServerField::Id => "id",
             ServerField::GeoPoint2d => "geo_point_2d",
             ServerField::GeoShape => "geo_shape",
             ServerField::Name => "name",
@@ -182,39 +274,250 @@ impl ServerField {
     }
 }
 
+/// A struct containing only a subset of the fields of [`Entry`].
+///
+/// This trait should not be implemented manually, use the [`projection!`] macro instead. In this
+/// way the fields of the struct and the ones requested to the server cannot disagree.
+///
+/// ```
+/// use workshop_rustlab_2022::database::{projection, Projection, ServerField, ServerQuery};
+///
+/// projection! {
+///     #[derive(Debug)]
+///     pub struct CustomEntry {
+///         Name,
+///         GeoPoint2d,
+///     }
+/// }
+///
+/// assert_eq!(CustomEntry::FIELDS, [ServerField::Name, ServerField::GeoPoint2d]);
+/// let query = ServerQuery::for_projection::<CustomEntry>();
+/// assert_eq!(query.fields.len(), 2);
+/// ```
+pub trait Projection: DeserializeOwned {
+    /// The fields contained in the projection.
+    const FIELDS: &'static [ServerField];
+}
+
+pub mod field_type {
+    //! The types of the fields of [`RawEntry`], named after the corresponding [`ServerField`].
+    //!
+    //! These are used by the [`projection!`] macro to generate structs with the same field types
+    //! of [`RawEntry`], which is the representation sent by the server.
+    //!
+    //! [`RawEntry`]: super::RawEntry
+    //! [`ServerField`]: super::ServerField
+    //! [`projection!`]: super::projection
+
+    pub type Id = super::EntryId;
+    pub type GeoPoint2d = super::GeoPoint2d;
+    pub type GeoShape = super::GeoShape;
+    pub type Name = String;
+    pub type Etichetta = String;
+    pub type Notetesto = String;
+    pub type Numeroantico = String;
+    pub type Numeromoderno = String;
+    pub type Link1 = String;
+    pub type Link2 = String;
+    pub type Link3 = String;
+    pub type Piani = String;
+    pub type Arcate = String;
+    pub type Architravate = String;
+    pub type ArchitravateConColonneDiLegno = String;
+    pub type Archivolti = String;
+    pub type Modiglioni = String;
+    pub type MensoloniArchitravati = String;
+    pub type StallaE = String;
+    pub type FienileI = String;
+    pub type RimessaE = String;
+    pub type ScuderiaE = String;
+    pub type AttivitaCommercialiProduttive1 = String;
+    pub type AttivitaCommercialiProduttive2 = String;
+    pub type AttivitaCommercialiProduttive3 = String;
+    pub type AttivitaCommercialiProduttive4 = String;
+    pub type AttivitaCommercialiProduttive5 = String;
+}
+
 /// The number of fields in [`Entry`].
-pub const FIELDS_LEN: u8 = 26;
+pub const FIELDS_LEN: u8 = 27;
 
 /// The default page size for a request.
 ///
 /// See [`ServerQuery::page_size`].
 pub const DEFAULT_PAGE_SIZE: u16 = 10;
 
+/// The cost of a write operation on a single entry.
+///
+/// Writes are more expensive than reads: this is twice the cost of reading all the fields of an
+/// entry.
+pub const WRITE_COST: u16 = 52;
+
+/// The ratio between the cost of a query and the cost of the same query answered with
+/// `304 Not Modified`.
+///
+/// See [`calc_not_modified_cost`].
+pub const NOT_MODIFIED_COST_RATIO: u16 = 10;
+
 /// The default max bucket capacity.
 pub const MAX_BUCKET_CAPACITY: u16 = 500;
 
 /// The default leaky bucket leak-per-second.
 pub const LEAK_PER_SECOND: u8 = 4;
 
-/// Calculate the cost of a given query.
+/// Calculate the expected cost of a given query with the default [`WeightedCost`].
+///
+/// Each field of each entry of a full page costs one point, except for the identifier which is free
+/// and for simplified geometries (see [`ServerQuery::simplify`]) which cost half a point, rounding
+/// up. The cost is never zero. The server reserves this cost, then refunds the points of the
+/// entries missing from the page.
 ///
 /// This is useful to evaluate if a [`LeakyBucket`] has enough free capacity to handle a specific
-/// request.
+/// request, when the server uses the default cost model.
 ///
 /// [`LeakyBucket`]: crate::leaky_bucket::LeakyBucket
 #[must_use]
 pub fn calc_query_cost(query: &ServerQuery) -> u16 {
-    let fields_cost = query
-        .fields
-        .is_empty()
-        .not()
-        .then_some(query.fields.len())
-        .unwrap_or_else(|| FIELDS_LEN.into())
-        .try_into()
-        .unwrap_or(u16::MAX);
-
-    query
-        .page_size
-        .unwrap_or(DEFAULT_PAGE_SIZE)
-        .saturating_mul(fields_cost)
+    WeightedCost::default().query_cost(query)
+}
+
+/// Calculate the cost of a query answered with `304 Not Modified`, with the default
+/// [`WeightedCost`].
+///
+/// When the `If-None-Match` header of a request matches the `ETag` of the response, the server
+/// does not send the entries again and only charges a fraction of their cost (see
+/// [`NOT_MODIFIED_COST_RATIO`]), rounding up. The cost is never zero.
+///
+/// This is the expected cost, a fraction of [`calc_query_cost`].
+#[must_use]
+pub fn calc_not_modified_cost(query: &ServerQuery) -> u16 {
+    WeightedCost::default().not_modified_cost(calc_query_cost(query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    projection! {
+        #[derive(Debug)]
+        struct NameAndPoint {
+            Name,
+            GeoPoint2d,
+            AttivitaCommercialiProduttive1,
+        }
+    }
+
+    #[test]
+    fn projection_fields() {
+        assert_eq!(
+            NameAndPoint::FIELDS,
+            [
+                ServerField::Name,
+                ServerField::GeoPoint2d,
+                ServerField::AttivitaCommercialiProduttive1
+            ],
+        );
+
+        let query = ServerQuery::for_projection::<NameAndPoint>();
+        assert_eq!(query.fields, NameAndPoint::FIELDS.iter().copied().collect());
+        assert_eq!(calc_query_cost(&query), DEFAULT_PAGE_SIZE * 3);
+    }
+
+    #[test]
+    fn projection_deserialize() {
+        let entry: NameAndPoint = serde_json::from_str(
+            r#"{
+                "name": "Casa",
+                "geo_point_2d": { "lon": 11.3, "lat": 44.5 },
+                "attivita_commerciali_produttive_1": "osteria"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(entry.name, "Casa");
+        assert!((entry.geo_point_2d.lon - 11.3).abs() < f64::EPSILON);
+        assert!((entry.geo_point_2d.lat - 44.5).abs() < f64::EPSILON);
+        assert_eq!(entry.attivita_commerciali_produttive_1, "osteria");
+    }
+
+    projection! {
+        struct AllFields {
+            Id,
+            GeoPoint2d,
+            GeoShape,
+            Name,
+            Etichetta,
+            Notetesto,
+            Numeroantico,
+            Numeromoderno,
+            Link1,
+            Link2,
+            Link3,
+            Piani,
+            Arcate,
+            Architravate,
+            ArchitravateConColonneDiLegno,
+            Archivolti,
+            Modiglioni,
+            MensoloniArchitravati,
+            StallaE,
+            FienileI,
+            RimessaE,
+            ScuderiaE,
+            AttivitaCommercialiProduttive1,
+            AttivitaCommercialiProduttive2,
+            AttivitaCommercialiProduttive3,
+            AttivitaCommercialiProduttive4,
+            AttivitaCommercialiProduttive5,
+        }
+    }
+
+    /// The [`projection!`] macro keeps its own table of the fields: a missing variant does not
+    /// compile, while a wrong field name fails to deserialize.
+    #[test]
+    fn projection_fields_table() {
+        assert_eq!(AllFields::FIELDS, ServerField::ALL);
+
+        let json = serde_json::to_string(&fixtures::raw_entry()).unwrap();
+        let entry: AllFields = serde_json::from_str(&json).unwrap();
+        assert_eq!(entry.id, EntryId(7));
+        assert_eq!(entry.attivita_commerciali_produttive_1, "osteria");
+    }
+
+    #[test]
+    fn simplified_geometries_cost() {
+        let mut query = ServerQuery {
+            page_size: Some(5),
+            ..ServerQuery::default()
+        };
+        assert_eq!(calc_query_cost(&query), 5 * 26);
+
+        query.simplify = NonZeroU16::new(10);
+        assert_eq!(calc_query_cost(&query), 5 * 25 + 3);
+
+        query.fields = [ServerField::Name, ServerField::GeoShape].into();
+        assert_eq!(calc_query_cost(&query), 5 + 3);
+
+        query.fields = [ServerField::Name, ServerField::GeoPoint2d].into();
+        assert_eq!(calc_query_cost(&query), 5 * 2);
+    }
+
+    #[test]
+    fn zero_tolerance_is_rejected() {
+        let query: ServerQuery = serde_json::from_str(r#"{ "simplify": 10 }"#).unwrap();
+        assert_eq!(query.simplify, NonZeroU16::new(10));
+        assert!(serde_json::from_str::<ServerQuery>(r#"{ "simplify": 0 }"#).is_err());
+    }
+
+    #[test]
+    fn not_modified_cost() {
+        let query = ServerQuery::default();
+        assert_eq!(calc_not_modified_cost(&query), 26);
+
+        let query = ServerQuery {
+            fields: [ServerField::Name].into(),
+            page_size: Some(1),
+            ..ServerQuery::default()
+        };
+        assert_eq!(calc_not_modified_cost(&query), 1);
+    }
 }
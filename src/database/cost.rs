@@ -0,0 +1,366 @@
+#![warn(clippy::pedantic)]
+
+//! The models computing the cost of the operations.
+//!
+//! The server charges the operations following a [`CostModel`] and advertises it at
+//! [`COST_MODEL_PATH`], therefore the clients can estimate the costs locally. Unless configured
+//! otherwise, the model is the default [`WeightedCost`], which charges one point for each field of
+//! each entry of the page. Each client is charged following the model scaled by the multiplier of
+//! its tier, see [`ScaledCost`].
+
+use std::{collections::BTreeMap, fmt::Debug, num::NonZeroU32};
+
+use serde::{Deserialize, Serialize};
+
+use super::{ServerField, ServerQuery, DEFAULT_PAGE_SIZE, NOT_MODIFIED_COST_RATIO, WRITE_COST};
+
+/// The path at which the server advertises its cost model as JSON, relative to its base URL.
+pub const COST_MODEL_PATH: &str = "cost-model";
+
+/// A way of computing the cost of the operations.
+///
+/// The cost of a query is expected before running it, from the query alone, and the server
+/// reserves it in the bucket. The actual cost is only known once the response is built: the unused
+/// points are then refunded (see [`BUCKET_REFUND_HEADER`]), while the missing ones are charged
+/// anyway.
+///
+/// [`BUCKET_REFUND_HEADER`]: crate::BUCKET_REFUND_HEADER
+pub trait CostModel: Debug {
+    /// Returns the expected cost of a query.
+    ///
+    /// This is useful to evaluate if a [`LeakyBucket`] has enough free capacity to handle a
+    /// specific request.
+    ///
+    /// [`LeakyBucket`]: crate::leaky_bucket::LeakyBucket
+    fn query_cost(&self, query: &ServerQuery) -> u16;
+
+    /// Returns the actual cost of a query, given the number of entries and the size in bytes of
+    /// the body of its response.
+    fn response_cost(&self, query: &ServerQuery, entries: usize, bytes: usize) -> u16;
+
+    /// Returns the cost of a response answered with `304 Not Modified`, given the cost of the
+    /// whole response.
+    ///
+    /// By default it is a fraction of the whole cost (see [`NOT_MODIFIED_COST_RATIO`]), rounding
+    /// up. The cost is never zero.
+    fn not_modified_cost(&self, cost: u16) -> u16 {
+        cost.div_ceil(NOT_MODIFIED_COST_RATIO).max(1)
+    }
+
+    /// Returns the cost of a write operation on a single entry.
+    fn write_cost(&self) -> u16 {
+        WRITE_COST
+    }
+}
+
+impl<M: CostModel + ?Sized> CostModel for &M {
+    fn query_cost(&self, query: &ServerQuery) -> u16 {
+        (**self).query_cost(query)
+    }
+
+    fn response_cost(&self, query: &ServerQuery, entries: usize, bytes: usize) -> u16 {
+        (**self).response_cost(query, entries, bytes)
+    }
+
+    fn not_modified_cost(&self, cost: u16) -> u16 {
+        (**self).not_modified_cost(cost)
+    }
+
+    fn write_cost(&self) -> u16 {
+        (**self).write_cost()
+    }
+}
+
+/// A cost model assigning a weight to each field.
+///
+/// The cost of an entry is the sum of the weights of the requested fields, all of them when no
+/// field is requested. The identifier is free, unless it is given a weight. The expected cost of a
+/// query is the one of a full page, while the actual cost depends on [`ChargeBy`], by default the
+/// entries actually returned. The costs are rounded up once for the whole response, and they are
+/// never zero: otherwise the queries requesting only free fields would never be throttled.
+///
+/// The model can be deserialized, the missing values keeping their default. For example, the
+/// following model charges the geometries as ten fields and always charges full pages:
+///
+/// ```json
+/// { "weights": { "geo_shape": 10 }, "charge_by": "page_size" }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeightedCost {
+    /// The weights of the fields, in points per entry.
+    ///
+    /// The fields missing from the map weigh [`WeightedCost::default_weight`], except for
+    /// [`ServerField::Id`] which is free.
+    pub weights: BTreeMap<ServerField, f64>,
+
+    /// The weight of the fields missing from [`WeightedCost::weights`], one point by default.
+    pub default_weight: f64,
+
+    /// The factor applied to the weight of [`ServerField::GeoShape`] when the geometries are
+    /// simplified (see [`ServerQuery::simplify`]), one half by default.
+    pub simplified_geo_shape_ratio: f64,
+
+    /// What the actual cost of a query is based on.
+    pub charge_by: ChargeBy,
+
+    /// The cost of a write operation on a single entry, [`WRITE_COST`] by default.
+    pub write_cost: u16,
+}
+
+/// What the actual cost of a query is based on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargeBy {
+    /// The page size, even if the page contains less entries.
+    PageSize,
+
+    /// The entries actually returned.
+    #[default]
+    Entries,
+
+    /// The size of the body of the response, each point paying for the given number of bytes.
+    ///
+    /// The weights are only used for the expected cost, therefore they should approximate the
+    /// size of the fields in points.
+    Bytes(NonZeroU32),
+}
+
+impl WeightedCost {
+    /// Assigns the given weight to a field.
+    #[must_use]
+    pub fn with_weight(mut self, field: ServerField, weight: f64) -> Self {
+        self.weights.insert(field, weight);
+        self
+    }
+
+    /// Sets what the actual cost of a query is based on.
+    #[must_use]
+    pub const fn with_charge_by(mut self, charge_by: ChargeBy) -> Self {
+        self.charge_by = charge_by;
+        self
+    }
+
+    /// Returns the cost of a single entry of the response to the given query.
+    fn entry_cost(&self, query: &ServerQuery) -> f64 {
+        let weight = |field| {
+            let weight = self
+                .weights
+                .get(&field)
+                .copied()
+                .unwrap_or(if field == ServerField::Id {
+                    0.
+                } else {
+                    self.default_weight
+                });
+            if field == ServerField::GeoShape && query.simplify.is_some() {
+                weight * self.simplified_geo_shape_ratio
+            } else {
+                weight
+            }
+        };
+
+        if query.fields.is_empty() {
+            ServerField::ALL.into_iter().map(weight).sum()
+        } else {
+            query.fields.iter().copied().map(weight).sum()
+        }
+    }
+}
+
+impl Default for WeightedCost {
+    fn default() -> Self {
+        Self {
+            weights: BTreeMap::new(),
+            default_weight: 1.,
+            simplified_geo_shape_ratio: 0.5,
+            charge_by: ChargeBy::default(),
+            write_cost: WRITE_COST,
+        }
+    }
+}
+
+impl CostModel for WeightedCost {
+    fn query_cost(&self, query: &ServerQuery) -> u16 {
+        let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        round_up(f64::from(page_size) * self.entry_cost(query)).max(1)
+    }
+
+    fn response_cost(&self, query: &ServerQuery, entries: usize, bytes: usize) -> u16 {
+        let cost = match self.charge_by {
+            ChargeBy::PageSize => self.query_cost(query),
+            ChargeBy::Entries => {
+                let entries = u32::try_from(entries).map_or(f64::from(u32::MAX), f64::from);
+                round_up(entries * self.entry_cost(query))
+            }
+            ChargeBy::Bytes(bytes_per_point) => {
+                let bytes_per_point = usize::try_from(bytes_per_point.get()).unwrap_or(usize::MAX);
+                u16::try_from(bytes.div_ceil(bytes_per_point)).unwrap_or(u16::MAX)
+            }
+        };
+        cost.max(1)
+    }
+
+    fn write_cost(&self) -> u16 {
+        self.write_cost
+    }
+}
+
+/// A cost model whose costs are multiplied by a factor, rounding up.
+///
+/// The server advertises this model to each client, with the multiplier of its tier: the JSON is
+/// the one of the inner model, with the additional `cost_multiplier` field. The cost of a
+/// `304 Not Modified` response is computed by the inner model from the cost already multiplied.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScaledCost<M = WeightedCost> {
+    /// The model computing the costs before the multiplier.
+    #[serde(flatten)]
+    pub model: M,
+
+    /// The factor applied to each cost, one by default.
+    #[serde(default = "default_cost_multiplier")]
+    pub cost_multiplier: f64,
+}
+
+const fn default_cost_multiplier() -> f64 {
+    1.
+}
+
+impl<M> ScaledCost<M> {
+    /// Creates the model multiplying the costs of `model` by `cost_multiplier`.
+    pub const fn new(model: M, cost_multiplier: f64) -> Self {
+        Self {
+            model,
+            cost_multiplier,
+        }
+    }
+
+    fn scale(&self, cost: u16) -> u16 {
+        round_up(f64::from(cost) * self.cost_multiplier)
+    }
+}
+
+impl<M: CostModel> CostModel for ScaledCost<M> {
+    fn query_cost(&self, query: &ServerQuery) -> u16 {
+        self.scale(self.model.query_cost(query))
+    }
+
+    fn response_cost(&self, query: &ServerQuery, entries: usize, bytes: usize) -> u16 {
+        self.scale(self.model.response_cost(query, entries, bytes))
+    }
+
+    fn not_modified_cost(&self, cost: u16) -> u16 {
+        self.model.not_modified_cost(cost)
+    }
+
+    fn write_cost(&self) -> u16 {
+        self.scale(self.model.write_cost())
+    }
+}
+
+/// Rounds up a cost, saturating.
+fn round_up(cost: f64) -> u16 {
+    // The conversion saturates, and negative costs are free.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let cost = cost.ceil() as u16;
+    cost
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU16;
+
+    use super::*;
+
+    #[test]
+    fn weights() {
+        let model = WeightedCost::default()
+            .with_weight(ServerField::GeoShape, 10.)
+            .with_weight(ServerField::Piani, 0.25);
+        let mut query = ServerQuery {
+            fields: [ServerField::GeoShape, ServerField::Piani].into(),
+            page_size: Some(5),
+            ..ServerQuery::default()
+        };
+        assert_eq!(model.query_cost(&query), 52);
+
+        query.simplify = NonZeroU16::new(10);
+        assert_eq!(model.query_cost(&query), 27);
+
+        // 24 fields weighing one point, plus the simplified geometry and the floors.
+        query.fields.clear();
+        assert_eq!(model.query_cost(&query), 147);
+
+        // Even a page of free fields costs a point.
+        query.fields = [ServerField::Id].into();
+        query.page_size = Some(u16::MAX);
+        assert_eq!(model.query_cost(&query), 1);
+        let model = model.with_weight(ServerField::Id, 0.001);
+        assert_eq!(model.query_cost(&query), 66);
+    }
+
+    #[test]
+    fn charge_by() {
+        let query = ServerQuery {
+            fields: [ServerField::Name, ServerField::GeoPoint2d].into(),
+            ..ServerQuery::default()
+        };
+
+        let model = WeightedCost::default();
+        assert_eq!(model.query_cost(&query), 20);
+        assert_eq!(model.response_cost(&query, 3, 100), 6);
+        assert_eq!(model.response_cost(&query, 0, 2), 1);
+
+        let model = model.with_charge_by(ChargeBy::PageSize);
+        assert_eq!(model.response_cost(&query, 3, 100), 20);
+
+        let model = model.with_charge_by(ChargeBy::Bytes(NonZeroU32::new(64).unwrap()));
+        assert_eq!(model.response_cost(&query, 3, 100), 2);
+        assert_eq!(model.response_cost(&query, 3, usize::MAX), u16::MAX);
+    }
+
+    #[test]
+    fn scaled() {
+        let query = ServerQuery {
+            fields: [ServerField::Name, ServerField::GeoPoint2d].into(),
+            ..ServerQuery::default()
+        };
+
+        let model = ScaledCost::new(WeightedCost::default(), 1.5);
+        assert_eq!(model.query_cost(&query), 30);
+        assert_eq!(model.response_cost(&query, 3, 100), 9);
+        assert_eq!(model.response_cost(&query, 0, 0), 2);
+        assert_eq!(model.not_modified_cost(30), 3);
+        assert_eq!(model.write_cost(), 78);
+
+        let model = ScaledCost::new(WeightedCost::default(), 2000.);
+        assert_eq!(model.write_cost(), u16::MAX);
+
+        let json = serde_json::to_value(&model).unwrap();
+        assert_eq!(json["cost_multiplier"], 2000.);
+        assert_eq!(json["write_cost"], WRITE_COST);
+        assert_eq!(serde_json::from_value::<ScaledCost>(json).unwrap(), model);
+
+        // A plain model is not scaled.
+        let json = serde_json::to_value(WeightedCost::default()).unwrap();
+        let model: ScaledCost = serde_json::from_value(json).unwrap();
+        assert_eq!(model, ScaledCost::new(WeightedCost::default(), 1.));
+    }
+
+    #[test]
+    fn deserialize() {
+        let model: WeightedCost = serde_json::from_str(
+            r#"{ "weights": { "geo_shape": 10 }, "charge_by": { "bytes": 512 } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            model,
+            WeightedCost::default()
+                .with_weight(ServerField::GeoShape, 10.)
+                .with_charge_by(ChargeBy::Bytes(NonZeroU32::new(512).unwrap()))
+        );
+
+        let json = serde_json::to_string(&model).unwrap();
+        assert_eq!(serde_json::from_str::<WeightedCost>(&json).unwrap(), model);
+    }
+}
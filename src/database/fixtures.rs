@@ -0,0 +1,83 @@
+#![warn(clippy::pedantic)]
+
+//! The entries shared by the tests.
+
+use super::{
+    geo_shape::{Feature, FeatureGeometry, Position},
+    raw::FLAG_SET,
+    EntryId, GeoPoint2d, GeoShape, RawEntry,
+};
+
+/// Returns an entry with all kinds of values.
+pub fn raw_entry() -> RawEntry {
+    RawEntry {
+        id: Some(EntryId(7)),
+        geo_point_2d: GeoPoint2d {
+            lon: 11.34,
+            lat: 44.49,
+        },
+        geo_shape: GeoShape::Feature(Feature {
+            geometry: FeatureGeometry::Polygon {
+                coordinates: vec![vec![
+                    Position::new(11.3, 44.4),
+                    Position::new(11.4, 44.5),
+                    Position::new(11.3, 44.4),
+                ]],
+            },
+            properties: None,
+            bbox: None,
+        }),
+        name: "Via Zamboni".to_owned(),
+        etichetta: String::new(),
+        notetesto: String::new(),
+        numeroantico: "1024".to_owned(),
+        numeromoderno: "33".to_owned(),
+        link1: "https://example.com/photo.jpg".to_owned(),
+        link2: String::new(),
+        link3: String::new(),
+        piani: "3".to_owned(),
+        arcate: String::new(),
+        architravate: String::new(),
+        architravate_con_colonne_di_legno: String::new(),
+        archivolti: String::new(),
+        modiglioni: String::new(),
+        mensoloni_architravati: String::new(),
+        stalla_e: FLAG_SET.to_owned(),
+        fienile_i: String::new(),
+        rimessa_e: String::new(),
+        scuderia_e: String::new(),
+        attivita_commerciali_produttive_1: "osteria".to_owned(),
+        attivita_commerciali_produttive_2: String::new(),
+        attivita_commerciali_produttive_3: String::new(),
+        attivita_commerciali_produttive_4: String::new(),
+        attivita_commerciali_produttive_5: String::new(),
+    }
+}
+
+/// Returns a small valid database, whose entries differ by name and position.
+#[cfg(feature = "server")]
+pub fn dataset(len: usize) -> Vec<RawEntry> {
+    (0..len)
+        .map(|index| {
+            // The positions are roughly ten meters apart.
+            let lon = 11.34 + 0.0001 * f64::from(u32::try_from(index).unwrap());
+            let lat = 44.49;
+            let square = [(0., 0.), (1., 0.), (1., 1.), (0., 1.), (0., 0.)]
+                .map(|(x, y)| Position::new(lon + x * 0.00005, lat + y * 0.00005));
+
+            RawEntry {
+                id: None,
+                geo_point_2d: GeoPoint2d { lon, lat },
+                geo_shape: GeoShape::Feature(Feature {
+                    geometry: FeatureGeometry::Polygon {
+                        coordinates: vec![square.to_vec()],
+                    },
+                    properties: None,
+                    bbox: None,
+                }),
+                name: format!("Entry {index}"),
+                ..raw_entry()
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,205 @@
+#![warn(clippy::pedantic)]
+
+//! The supported types of geographic shapes.
+//!
+//! The types follow the [GeoJSON] specification.
+//!
+//! [GeoJSON]: https://datatracker.ietf.org/doc/html/rfc7946
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[cfg(feature = "geo")]
+mod analytics;
+#[cfg(feature = "geo")]
+mod validation;
+
+#[cfg(feature = "geo")]
+pub use analytics::EARTH_RADIUS;
+#[cfg(feature = "geo")]
+pub use validation::{GeometryError, RingIndex};
+
+/// A GeoJSON feature.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Feature {
+    /// The geometry of the feature.
+    pub geometry: FeatureGeometry,
+
+    /// The properties associated to the feature, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<Map<String, Value>>,
+
+    /// The bounding box of the feature, if any.
+    ///
+    /// It contains all the minimum values for each axis, followed by the maximum ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<Vec<f64>>,
+}
+
+/// A GeoJSON geometry.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum FeatureGeometry {
+    Point {
+        coordinates: Position,
+    },
+    MultiPoint {
+        coordinates: Vec<Position>,
+    },
+    LineString {
+        coordinates: Vec<Position>,
+    },
+    MultiLineString {
+        coordinates: Vec<Vec<Position>>,
+    },
+
+    /// A polygon, made of a linear ring for the exterior followed by eventual holes.
+    Polygon {
+        coordinates: Vec<Vec<Position>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Position>>>,
+    },
+    GeometryCollection {
+        geometries: Vec<FeatureGeometry>,
+    },
+}
+
+/// A GeoJSON position, with an optional altitude.
+///
+/// It is represented as an array of two or three numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PositionRepr", into = "PositionRepr")]
+pub struct Position {
+    /// The longitude.
+    pub lon: f64,
+
+    /// The latitude.
+    pub lat: f64,
+
+    /// The altitude, if specified.
+    pub altitude: Option<f64>,
+}
+
+impl Position {
+    /// Creates a 2-D position.
+    #[must_use]
+    pub const fn new(lon: f64, lat: f64) -> Self {
+        Self {
+            lon,
+            lat,
+            altitude: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+enum PositionRepr {
+    Flat([f64; 2]),
+    WithAltitude([f64; 3]),
+}
+
+impl From<PositionRepr> for Position {
+    fn from(repr: PositionRepr) -> Self {
+        match repr {
+            PositionRepr::Flat([lon, lat]) => Self::new(lon, lat),
+            PositionRepr::WithAltitude([lon, lat, altitude]) => Self {
+                lon,
+                lat,
+                altitude: Some(altitude),
+            },
+        }
+    }
+}
+
+impl From<Position> for PositionRepr {
+    fn from(position: Position) -> Self {
+        let Position { lon, lat, altitude } = position;
+        match altitude {
+            Some(altitude) => Self::WithAltitude([lon, lat, altitude]),
+            None => Self::Flat([lon, lat]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let flat: Position = serde_json::from_value(json!([11.3, 44.5])).unwrap();
+        assert_eq!(flat, Position::new(11.3, 44.5));
+        assert_eq!(serde_json::to_value(flat).unwrap(), json!([11.3, 44.5]));
+
+        let with_altitude: Position = serde_json::from_value(json!([11.3, 44.5, 54.])).unwrap();
+        assert_eq!(with_altitude.altitude, Some(54.));
+        assert_eq!(
+            serde_json::to_value(with_altitude).unwrap(),
+            json!([11.3, 44.5, 54.])
+        );
+
+        assert!(serde_json::from_value::<Position>(json!([11.3])).is_err());
+        assert!(serde_json::from_value::<Position>(json!([11.3, 44.5, 0., 1.])).is_err());
+    }
+
+    #[test]
+    fn all_geometries() {
+        let geometries = json!([
+            { "type": "Point", "coordinates": [11.3, 44.5] },
+            { "type": "MultiPoint", "coordinates": [[11.3, 44.5], [11.4, 44.6, 0.]] },
+            { "type": "LineString", "coordinates": [[11.3, 44.5], [11.4, 44.6]] },
+            { "type": "MultiLineString", "coordinates": [[[11.3, 44.5], [11.4, 44.6]]] },
+            {
+                "type": "Polygon",
+                "coordinates": [[[11.3, 44.5, 0.], [11.4, 44.5, 0.], [11.3, 44.6, 0.], [11.3, 44.5, 0.]]]
+            },
+            {
+                "type": "MultiPolygon",
+                "coordinates": [[[[11.3, 44.5], [11.4, 44.5], [11.3, 44.6], [11.3, 44.5]]]]
+            },
+            {
+                "type": "GeometryCollection",
+                "geometries": [{ "type": "Point", "coordinates": [11.3, 44.5] }]
+            }
+        ]);
+
+        let parsed: Vec<FeatureGeometry> = serde_json::from_value(geometries.clone()).unwrap();
+        assert!(matches!(
+            parsed.last(),
+            Some(FeatureGeometry::GeometryCollection { geometries }) if geometries.len() == 1
+        ));
+        assert_eq!(serde_json::to_value(parsed).unwrap(), geometries);
+    }
+
+    #[test]
+    fn feature_optional_members() {
+        let feature = json!({
+            "geometry": { "type": "Point", "coordinates": [11.3, 44.5] },
+            "properties": { "name": "Torre degli Asinelli" },
+            "bbox": [11.3, 44.5, 11.3, 44.5]
+        });
+
+        let parsed: Feature = serde_json::from_value(feature.clone()).unwrap();
+        assert_eq!(
+            parsed
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.get("name")),
+            Some(&json!("Torre degli Asinelli"))
+        );
+        assert_eq!(parsed.bbox.as_deref(), Some(&[11.3, 44.5, 11.3, 44.5][..]));
+        assert_eq!(serde_json::to_value(parsed).unwrap(), feature);
+
+        let parsed: Feature = serde_json::from_value(json!({
+            "geometry": { "type": "Point", "coordinates": [11.3, 44.5] },
+            "properties": null
+        }))
+        .unwrap();
+        assert_eq!(parsed.properties, None);
+        assert_eq!(parsed.bbox, None);
+    }
+}
@@ -0,0 +1,455 @@
+#![warn(clippy::pedantic)]
+
+//! Geometric computations on [`FeatureGeometry`].
+//!
+//! Areas and lengths are _geodesic_, calculated on a sphere with radius [`EARTH_RADIUS`]. Centroids
+//! and simplifications work on planar coordinates, which is a good approximation for the small
+//! shapes of the database.
+
+use super::{FeatureGeometry, Position};
+
+/// The mean radius of the Earth, in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+impl FeatureGeometry {
+    /// Returns the geodesic area of the geometry, in square meters.
+    ///
+    /// Only polygons have an area, holes are subtracted from it.
+    #[must_use]
+    pub fn area(&self) -> f64 {
+        match self {
+            Self::Point { .. }
+            | Self::MultiPoint { .. }
+            | Self::LineString { .. }
+            | Self::MultiLineString { .. } => 0.,
+            Self::Polygon { coordinates } => polygon_area(coordinates),
+            Self::MultiPolygon { coordinates } => coordinates
+                .iter()
+                .map(|polygon| polygon_area(polygon))
+                .sum(),
+            Self::GeometryCollection { geometries } => {
+                geometries.iter().map(FeatureGeometry::area).sum()
+            }
+        }
+    }
+
+    /// Returns the geodesic perimeter of the geometry, in meters.
+    ///
+    /// Only polygons have a perimeter, which includes the boundaries of the holes.
+    #[must_use]
+    pub fn perimeter(&self) -> f64 {
+        match self {
+            Self::Point { .. }
+            | Self::MultiPoint { .. }
+            | Self::LineString { .. }
+            | Self::MultiLineString { .. } => 0.,
+            Self::Polygon { coordinates } => coordinates.iter().map(|ring| length(ring)).sum(),
+            Self::MultiPolygon { coordinates } => {
+                coordinates.iter().flatten().map(|ring| length(ring)).sum()
+            }
+            Self::GeometryCollection { geometries } => {
+                geometries.iter().map(FeatureGeometry::perimeter).sum()
+            }
+        }
+    }
+
+    /// Returns the centroid of the geometry, or `None` if the geometry is empty.
+    ///
+    /// Only the components with the highest dimension are taken into account: i.e., the points of
+    /// a collection containing a polygon are ignored. Degenerate polygons are treated like their
+    /// boundaries.
+    #[must_use]
+    pub fn centroid(&self) -> Option<Position> {
+        let mut centroid = Centroid::default();
+        centroid.add_geometry(self);
+        centroid.get()
+    }
+
+    /// Returns a simplified geometry using the [Douglas–Peucker] algorithm.
+    ///
+    /// `tolerance` is the maximum distance, in meters, between the original and the simplified
+    /// lines. Rings which would be simplified to less than four positions are kept unchanged, in
+    /// order to always obtain valid polygons.
+    ///
+    /// [Douglas–Peucker]: https://en.wikipedia.org/wiki/Ramer%E2%80%93Douglas%E2%80%93Peucker_algorithm
+    #[must_use]
+    pub fn simplify(&self, tolerance: f64) -> Self {
+        let simplify_polygon = |polygon: &Vec<Vec<Position>>| -> Vec<Vec<Position>> {
+            polygon
+                .iter()
+                .map(|ring| simplify_ring(ring, tolerance))
+                .collect()
+        };
+
+        match self {
+            Self::Point { .. } | Self::MultiPoint { .. } => self.clone(),
+            Self::LineString { coordinates } => Self::LineString {
+                coordinates: douglas_peucker(coordinates, tolerance),
+            },
+            Self::MultiLineString { coordinates } => Self::MultiLineString {
+                coordinates: coordinates
+                    .iter()
+                    .map(|line| douglas_peucker(line, tolerance))
+                    .collect(),
+            },
+            Self::Polygon { coordinates } => Self::Polygon {
+                coordinates: simplify_polygon(coordinates),
+            },
+            Self::MultiPolygon { coordinates } => Self::MultiPolygon {
+                coordinates: coordinates.iter().map(simplify_polygon).collect(),
+            },
+            Self::GeometryCollection { geometries } => Self::GeometryCollection {
+                geometries: geometries
+                    .iter()
+                    .map(|geometry| geometry.simplify(tolerance))
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// The geodesic area of a polygon, given its rings.
+fn polygon_area(rings: &[Vec<Position>]) -> f64 {
+    let mut rings = rings.iter().map(|ring| ring_area(ring));
+    let exterior = rings.next().unwrap_or(0.);
+    let holes: f64 = rings.sum();
+
+    (exterior - holes).max(0.)
+}
+
+/// The unsigned geodesic area of a linear ring.
+///
+/// See _Some Algorithms for Polygons on a Sphere_, Chamberlain and Duquette, JPL Publication 07-03.
+fn ring_area(ring: &[Position]) -> f64 {
+    let area: f64 = ring
+        .iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| {
+            (b.lon - a.lon).to_radians()
+                * (2. + a.lat.to_radians().sin() + b.lat.to_radians().sin())
+        })
+        .sum();
+
+    (area * EARTH_RADIUS * EARTH_RADIUS / 2.).abs()
+}
+
+/// The geodesic length of a line.
+fn length(line: &[Position]) -> f64 {
+    line.windows(2)
+        .map(|pair| haversine(pair[0], pair[1]))
+        .sum()
+}
+
+/// The great-circle distance between two positions, in meters.
+fn haversine(a: Position, b: Position) -> f64 {
+    let (a_lat, b_lat) = (a.lat.to_radians(), b.lat.to_radians());
+    let half_delta_lat = (b_lat - a_lat) / 2.;
+    let half_delta_lon = (b.lon - a.lon).to_radians() / 2.;
+
+    let h = half_delta_lat.sin().powi(2) + a_lat.cos() * b_lat.cos() * half_delta_lon.sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Weighted sums of positions, one for each dimension.
+///
+/// Index 0 contains points, index 1 contains segments weighted by their length, index 2 contains
+/// rings weighted by their area.
+#[derive(Default)]
+struct Centroid([WeightedSum; 3]);
+
+#[derive(Clone, Copy, Default)]
+struct WeightedSum {
+    weight: f64,
+    lon: f64,
+    lat: f64,
+}
+
+impl WeightedSum {
+    fn add(&mut self, weight: f64, lon: f64, lat: f64) {
+        self.weight += weight;
+        self.lon += weight * lon;
+        self.lat += weight * lat;
+    }
+}
+
+impl Centroid {
+    fn add_geometry(&mut self, geometry: &FeatureGeometry) {
+        match geometry {
+            FeatureGeometry::Point { coordinates } => self.add_points(&[*coordinates]),
+            FeatureGeometry::MultiPoint { coordinates } => self.add_points(coordinates),
+            FeatureGeometry::LineString { coordinates } => self.add_line(coordinates),
+            FeatureGeometry::MultiLineString { coordinates } => {
+                for line in coordinates {
+                    self.add_line(line);
+                }
+            }
+            FeatureGeometry::Polygon { coordinates } => self.add_polygon(coordinates),
+            FeatureGeometry::MultiPolygon { coordinates } => {
+                for polygon in coordinates {
+                    self.add_polygon(polygon);
+                }
+            }
+            FeatureGeometry::GeometryCollection { geometries } => {
+                for geometry in geometries {
+                    self.add_geometry(geometry);
+                }
+            }
+        }
+    }
+
+    fn add_points(&mut self, points: &[Position]) {
+        for point in points {
+            self.0[0].add(1., point.lon, point.lat);
+        }
+    }
+
+    fn add_line(&mut self, line: &[Position]) {
+        self.add_points(line);
+
+        for pair in line.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let length = (b.lon - a.lon).hypot(b.lat - a.lat);
+            self.0[1].add(length, a.lon.midpoint(b.lon), a.lat.midpoint(b.lat));
+        }
+    }
+
+    fn add_polygon(&mut self, rings: &[Vec<Position>]) {
+        for (index, ring) in rings.iter().enumerate() {
+            self.add_line(ring);
+
+            let Some((area, lon, lat)) = planar_ring_centroid(ring) else {
+                continue;
+            };
+
+            // Holes are subtracted from the exterior ring.
+            let area = if index == 0 { area } else { -area };
+            self.0[2].add(area, lon, lat);
+        }
+    }
+
+    fn get(&self) -> Option<Position> {
+        self.0
+            .iter()
+            .rev()
+            .find(|sum| sum.weight > 0.)
+            .map(|sum| Position::new(sum.lon / sum.weight, sum.lat / sum.weight))
+    }
+}
+
+/// Returns the unsigned planar area and the centroid of a ring, or `None` if the area is zero.
+///
+/// The coordinates are translated to the first position of the ring in order to reduce rounding
+/// errors.
+fn planar_ring_centroid(ring: &[Position]) -> Option<(f64, f64, f64)> {
+    let origin = ring.first()?;
+    let (doubled_area, lon, lat) = ring
+        .iter()
+        .zip(ring.iter().cycle().skip(1))
+        .map(|(a, b)| {
+            let (a_lon, a_lat) = (a.lon - origin.lon, a.lat - origin.lat);
+            let (b_lon, b_lat) = (b.lon - origin.lon, b.lat - origin.lat);
+            let cross = a_lon * b_lat - b_lon * a_lat;
+            (cross, (a_lon + b_lon) * cross, (a_lat + b_lat) * cross)
+        })
+        .fold((0., 0., 0.), |acc, cur| {
+            (acc.0 + cur.0, acc.1 + cur.1, acc.2 + cur.2)
+        });
+
+    (doubled_area != 0.).then(|| {
+        (
+            (doubled_area / 2.).abs(),
+            origin.lon + lon / (3. * doubled_area),
+            origin.lat + lat / (3. * doubled_area),
+        )
+    })
+}
+
+/// Simplifies a closed ring, returning the original one if it would degenerate.
+fn simplify_ring(ring: &[Position], tolerance: f64) -> Vec<Position> {
+    let simplified = douglas_peucker(ring, tolerance);
+    if simplified.len() < 4 {
+        ring.to_vec()
+    } else {
+        simplified
+    }
+}
+
+/// The Douglas–Peucker algorithm, using an equirectangular projection in order to evaluate the
+/// distances in meters.
+fn douglas_peucker(line: &[Position], tolerance: f64) -> Vec<Position> {
+    let (Some(first), Some(last_index)) = (line.first(), line.len().checked_sub(1)) else {
+        return Vec::new();
+    };
+
+    let scale = EARTH_RADIUS.to_radians();
+    let lon_scale = scale * first.lat.to_radians().cos();
+    let projected: Vec<_> = line
+        .iter()
+        .map(|position| (position.lon * lon_scale, position.lat * scale))
+        .collect();
+
+    let mut keep = vec![false; line.len()];
+    keep[0] = true;
+    keep[last_index] = true;
+
+    let mut ranges = vec![(0, last_index)];
+    while let Some((start, end)) = ranges.pop() {
+        let farthest = (start + 1..end)
+            .map(|index| {
+                let distance = segment_distance(projected[index], projected[start], projected[end]);
+                (index, distance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        if let Some((index, distance)) = farthest {
+            if distance > tolerance {
+                keep[index] = true;
+                ranges.push((start, index));
+                ranges.push((index, end));
+            }
+        }
+    }
+
+    line.iter()
+        .zip(keep)
+        .filter_map(|(position, keep)| keep.then_some(*position))
+        .collect()
+}
+
+/// The distance between a point and a segment on a plane.
+fn segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let squared_length = dx * dx + dy * dy;
+
+    let t = if squared_length == 0. {
+        0.
+    } else {
+        (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / squared_length).clamp(0., 1.)
+    };
+
+    (point.0 - (start.0 + t * dx)).hypot(point.1 - (start.1 + t * dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A square of 0.001° × 0.001°, with a square hole of 0.0005° × 0.0005°, on the equator.
+    fn square_with_hole() -> FeatureGeometry {
+        let ring = |size: f64| {
+            vec![
+                Position::new(0., 0.),
+                Position::new(size, 0.),
+                Position::new(size, size),
+                Position::new(0., size),
+                Position::new(0., 0.),
+            ]
+        };
+
+        FeatureGeometry::Polygon {
+            coordinates: vec![ring(0.001), ring(0.0005)],
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn area_and_perimeter() {
+        let side = EARTH_RADIUS.to_radians() * 0.001;
+        let polygon = square_with_hole();
+
+        assert_close(polygon.area(), side * side * 0.75, 1.);
+        assert_close(polygon.perimeter(), side * 6., 0.01);
+
+        let point = FeatureGeometry::Point {
+            coordinates: Position::new(0., 0.),
+        };
+        assert_close(point.area(), 0., 0.);
+        assert_close(point.perimeter(), 0., 0.);
+
+        let collection = FeatureGeometry::GeometryCollection {
+            geometries: vec![polygon.clone(), point, polygon],
+        };
+        assert_close(collection.area(), side * side * 1.5, 2.);
+    }
+
+    #[test]
+    fn centroid() {
+        let square = FeatureGeometry::Polygon {
+            coordinates: vec![vec![
+                Position::new(11., 44.),
+                Position::new(11.002, 44.),
+                Position::new(11.002, 44.002),
+                Position::new(11., 44.002),
+                Position::new(11., 44.),
+            ]],
+        };
+        let centroid = square.centroid().unwrap();
+        assert_close(centroid.lon, 11.001, 1e-9);
+        assert_close(centroid.lat, 44.001, 1e-9);
+
+        // The hole moves the centroid away from the bottom-left corner.
+        let centroid = square_with_hole().centroid().unwrap();
+        assert_close(centroid.lon, 0.000_583_333, 1e-9);
+        assert_close(centroid.lat, 0.000_583_333, 1e-9);
+
+        let line = FeatureGeometry::LineString {
+            coordinates: vec![Position::new(0., 0.), Position::new(2., 0.)],
+        };
+        let collection = FeatureGeometry::GeometryCollection {
+            geometries: vec![
+                line,
+                FeatureGeometry::Point {
+                    coordinates: Position::new(10., 10.),
+                },
+            ],
+        };
+        assert_eq!(collection.centroid(), Some(Position::new(1., 0.)));
+
+        let empty = FeatureGeometry::MultiPoint {
+            coordinates: Vec::new(),
+        };
+        assert_eq!(empty.centroid(), None);
+    }
+
+    #[test]
+    fn simplify() {
+        // ~1 m and ~11 m away from the straight line.
+        let line = FeatureGeometry::LineString {
+            coordinates: vec![
+                Position::new(0., 0.),
+                Position::new(0.0005, 0.000_01),
+                Position::new(0.001, 0.),
+                Position::new(0.0015, 0.0001),
+                Position::new(0.002, 0.),
+            ],
+        };
+
+        let FeatureGeometry::LineString { coordinates } = line.simplify(5.) else {
+            unreachable!();
+        };
+        assert_eq!(
+            coordinates,
+            [
+                Position::new(0., 0.),
+                Position::new(0.001, 0.),
+                Position::new(0.0015, 0.0001),
+                Position::new(0.002, 0.),
+            ]
+        );
+
+        let FeatureGeometry::LineString { coordinates } = line.simplify(0.5) else {
+            unreachable!();
+        };
+        assert_eq!(coordinates.len(), 5);
+
+        // Rings never degenerate.
+        let polygon = square_with_hole();
+        assert_eq!(polygon.simplify(1000.), polygon);
+    }
+}
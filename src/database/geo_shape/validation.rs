@@ -0,0 +1,252 @@
+#![warn(clippy::pedantic)]
+
+//! Validation and containment checks on the polygons of a [`FeatureGeometry`].
+
+use std::{
+    cmp::Ordering,
+    fmt::{self, Display},
+    ops::Not,
+};
+
+use super::{FeatureGeometry, Position};
+
+impl FeatureGeometry {
+    /// Returns all the problems of the polygons contained in the geometry.
+    ///
+    /// Each ring must have at least four positions, it must be closed (the first and the last
+    /// positions are the same) and it must not intersect itself. Other kinds of geometry are
+    /// always considered valid.
+    #[must_use]
+    pub fn validate(&self) -> Vec<GeometryError> {
+        let mut errors = Vec::new();
+        for (polygon_index, polygon) in self.polygons().enumerate() {
+            for (ring_index, ring) in polygon.iter().enumerate() {
+                let ring_index = RingIndex {
+                    polygon: polygon_index,
+                    ring: ring_index,
+                };
+                validate_ring(ring, ring_index, &mut errors);
+            }
+        }
+
+        errors
+    }
+
+    /// Returns whether the position is inside one of the polygons of the geometry.
+    ///
+    /// Positions inside the holes of a polygon are considered outside of it. Other kinds of
+    /// geometry never contain a position.
+    #[must_use]
+    pub fn contains(&self, position: Position) -> bool {
+        self.polygons().any(|polygon| {
+            let mut rings = polygon.iter();
+            rings
+                .next()
+                .is_some_and(|exterior| ring_contains(exterior, position))
+                && rings.all(|hole| ring_contains(hole, position).not())
+        })
+    }
+
+    /// Returns an iterator over all the polygons of the geometry, in order.
+    fn polygons(&self) -> Box<dyn Iterator<Item = &[Vec<Position>]> + '_> {
+        match self {
+            Self::Point { .. }
+            | Self::MultiPoint { .. }
+            | Self::LineString { .. }
+            | Self::MultiLineString { .. } => Box::new(std::iter::empty()),
+            Self::Polygon { coordinates } => Box::new(std::iter::once(coordinates.as_slice())),
+            Self::MultiPolygon { coordinates } => Box::new(coordinates.iter().map(Vec::as_slice)),
+            Self::GeometryCollection { geometries } => {
+                Box::new(geometries.iter().flat_map(FeatureGeometry::polygons))
+            }
+        }
+    }
+}
+
+/// The position of a ring inside a [`FeatureGeometry`].
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct RingIndex {
+    /// The index of the polygon, counting all the polygons in the geometry in order.
+    pub polygon: usize,
+
+    /// The index of the ring inside the polygon, `0` being the exterior ring.
+    pub ring: usize,
+}
+
+/// A problem found by [`FeatureGeometry::validate`].
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum GeometryError {
+    /// The ring has less than four positions.
+    TooFewPositions(RingIndex),
+
+    /// The first and the last positions of the ring are different.
+    UnclosedRing(RingIndex),
+
+    /// Two non-adjacent segments of the ring intersect.
+    SelfIntersection {
+        /// The ring.
+        ring: RingIndex,
+
+        /// The indices of the intersecting segments.
+        ///
+        /// The segment `i` goes from the position `i` to the position `i + 1`.
+        segments: (usize, usize),
+    },
+}
+
+impl Display for GeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeometryError::TooFewPositions(ring) => {
+                write!(f, "{ring} has less than four positions")
+            }
+            GeometryError::UnclosedRing(ring) => write!(f, "{ring} is not closed"),
+            GeometryError::SelfIntersection {
+                ring,
+                segments: (a, b),
+            } => write!(f, "{ring} intersects itself at segments {a} and {b}"),
+        }
+    }
+}
+
+impl Display for RingIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ring {} of polygon {}", self.ring, self.polygon)
+    }
+}
+
+fn validate_ring(ring: &[Position], index: RingIndex, errors: &mut Vec<GeometryError>) {
+    if ring.len() < 4 {
+        errors.push(GeometryError::TooFewPositions(index));
+        return;
+    }
+
+    // A closed ring repeats exactly the same position, therefore strict comparisons are fine.
+    let (first, last) = (ring[0], ring[ring.len() - 1]);
+    #[allow(clippy::float_cmp)]
+    if first.lon != last.lon || first.lat != last.lat {
+        errors.push(GeometryError::UnclosedRing(index));
+        return;
+    }
+
+    let segments = ring.len() - 1;
+    for a in 0..segments {
+        // Adjacent segments always share a position, including the first and the last ones.
+        let last_b = if a == 0 { segments - 1 } else { segments };
+        for b in (a + 2)..last_b {
+            if segments_intersect((ring[a], ring[a + 1]), (ring[b], ring[b + 1])) {
+                errors.push(GeometryError::SelfIntersection {
+                    ring: index,
+                    segments: (a, b),
+                });
+            }
+        }
+    }
+}
+
+/// Checks whether two segments intersect, including touching and overlapping segments.
+fn segments_intersect(a: (Position, Position), b: (Position, Position)) -> bool {
+    let orientation = |p: Position, q: Position, r: Position| {
+        ((q.lon - p.lon) * (r.lat - p.lat) - (q.lat - p.lat) * (r.lon - p.lon))
+            .partial_cmp(&0.)
+            .unwrap_or(Ordering::Equal)
+    };
+    let on_segment = |p: Position, q: Position, r: Position| {
+        r.lon >= p.lon.min(q.lon)
+            && r.lon <= p.lon.max(q.lon)
+            && r.lat >= p.lat.min(q.lat)
+            && r.lat <= p.lat.max(q.lat)
+    };
+
+    let o1 = orientation(a.0, a.1, b.0);
+    let o2 = orientation(a.0, a.1, b.1);
+    let o3 = orientation(b.0, b.1, a.0);
+    let o4 = orientation(b.0, b.1, a.1);
+
+    (o1 != o2 && o3 != o4)
+        || (o1 == Ordering::Equal && on_segment(a.0, a.1, b.0))
+        || (o2 == Ordering::Equal && on_segment(a.0, a.1, b.1))
+        || (o3 == Ordering::Equal && on_segment(b.0, b.1, a.0))
+        || (o4 == Ordering::Equal && on_segment(b.0, b.1, a.1))
+}
+
+/// Ray casting point-in-polygon test.
+fn ring_contains(ring: &[Position], position: Position) -> bool {
+    ring.iter()
+        .zip(ring.iter().cycle().skip(1))
+        .filter(|(a, b)| {
+            (a.lat > position.lat) != (b.lat > position.lat)
+                && position.lon < (b.lon - a.lon) * (position.lat - a.lat) / (b.lat - a.lat) + a.lon
+        })
+        .count()
+        % 2
+        == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn polygon(rings: &[&[(f64, f64)]]) -> FeatureGeometry {
+        FeatureGeometry::Polygon {
+            coordinates: rings
+                .iter()
+                .map(|ring| {
+                    ring.iter()
+                        .map(|&(lon, lat)| Position::new(lon, lat))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+
+    const SQUARE: &[(f64, f64)] = &[(0., 0.), (2., 0.), (2., 2.), (0., 2.), (0., 0.)];
+    const HOLE: &[(f64, f64)] = &[(0.5, 0.5), (1., 0.5), (1., 1.), (0.5, 1.), (0.5, 0.5)];
+
+    #[test]
+    fn valid_polygon() {
+        assert_eq!(polygon(&[SQUARE, HOLE]).validate(), []);
+    }
+
+    #[test]
+    fn invalid_rings() {
+        let first = RingIndex {
+            polygon: 0,
+            ring: 0,
+        };
+        let second = RingIndex {
+            polygon: 0,
+            ring: 1,
+        };
+
+        assert_eq!(
+            polygon(&[&SQUARE[..4], &HOLE[..3]]).validate(),
+            [
+                GeometryError::UnclosedRing(first),
+                GeometryError::TooFewPositions(second),
+            ]
+        );
+
+        let bow_tie: &[(f64, f64)] = &[(0., 0.), (2., 2.), (2., 0.), (0., 2.), (0., 0.)];
+        assert_eq!(
+            polygon(&[bow_tie]).validate(),
+            [GeometryError::SelfIntersection {
+                ring: first,
+                segments: (0, 2)
+            }]
+        );
+    }
+
+    #[test]
+    fn contains() {
+        let geometry = polygon(&[SQUARE, HOLE]);
+        assert!(geometry.contains(Position::new(1.5, 1.5)));
+        assert!(geometry.contains(Position::new(0.75, 0.75)).not());
+        assert!(geometry.contains(Position::new(3., 1.)).not());
+
+        let point = FeatureGeometry::Point {
+            coordinates: Position::new(1., 1.),
+        };
+        assert!(point.contains(Position::new(1., 1.)).not());
+    }
+}
@@ -0,0 +1,449 @@
+#![warn(clippy::pedantic)]
+
+//! The raw representation of the database entries, in which every attribute is a string.
+//!
+//! This is the format of both the JSON database and the server responses. Use [`Entry`] in order
+//! to work with typed values.
+
+use std::{
+    fmt::{self, Display},
+    ops::Not,
+};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{Entry, EntryId, GeoPoint2d, GeoShape, ServerField};
+
+/// A single entry of the database, as it is stored.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RawEntry {
+    /// The identifier of the entry, missing if the entry has not been stored by the server yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<EntryId>,
+    pub geo_point_2d: GeoPoint2d,
+    pub geo_shape: GeoShape,
+    pub name: String,
+    pub etichetta: String,
+    pub notetesto: String,
+    pub numeroantico: String,
+    pub numeromoderno: String,
+    pub link1: String,
+    pub link2: String,
+    pub link3: String,
+    pub piani: String,
+    pub arcate: String,
+    pub architravate: String,
+    pub architravate_con_colonne_di_legno: String,
+    pub archivolti: String,
+    pub modiglioni: String,
+    pub mensoloni_architravati: String,
+    pub stalla_e: String,
+    pub fienile_i: String,
+    pub rimessa_e: String,
+    pub scuderia_e: String,
+    pub attivita_commerciali_produttive_1: String,
+    pub attivita_commerciali_produttive_2: String,
+    pub attivita_commerciali_produttive_3: String,
+    pub attivita_commerciali_produttive_4: String,
+    pub attivita_commerciali_produttive_5: String,
+}
+
+/// The raw value of a flag that is set.
+///
+/// A flag that is not set is represented by an empty string.
+pub const FLAG_SET: &str = "X";
+
+/// A typed value which can be converted from and to a raw string.
+trait RawValue: Sized + Default {
+    fn from_raw(raw: &str) -> Result<Self, FieldErrorKind>;
+
+    fn to_raw(&self) -> String;
+}
+
+macro_rules! impl_raw_number {
+    ($($ty:ty),*) => {
+        $(
+            impl RawValue for Option<$ty> {
+                fn from_raw(raw: &str) -> Result<Self, FieldErrorKind> {
+                    if raw.is_empty() {
+                        return Ok(None);
+                    }
+
+                    let (integer, fraction) = raw.split_once('.').unwrap_or((raw, ""));
+                    if fraction.bytes().all(|b| b.is_ascii_digit()).not() {
+                        return Err(FieldErrorKind::InvalidNumber);
+                    }
+
+                    let value = integer.parse().map_err(|_| FieldErrorKind::InvalidNumber)?;
+                    if fraction.bytes().all(|b| b == b'0') {
+                        Ok(Some(value))
+                    } else {
+                        Err(FieldErrorKind::NotLossless)
+                    }
+                }
+
+                fn to_raw(&self) -> String {
+                    self.map(|value| value.to_string()).unwrap_or_default()
+                }
+            }
+        )*
+    };
+}
+
+impl_raw_number!(u8, u32);
+
+impl RawValue for bool {
+    fn from_raw(raw: &str) -> Result<Self, FieldErrorKind> {
+        match raw {
+            "" => Ok(false),
+            FLAG_SET => Ok(true),
+            _ => Err(FieldErrorKind::InvalidFlag),
+        }
+    }
+
+    fn to_raw(&self) -> String {
+        if *self { FLAG_SET } else { "" }.to_owned()
+    }
+}
+
+impl RawValue for Option<Url> {
+    fn from_raw(raw: &str) -> Result<Self, FieldErrorKind> {
+        if raw.is_empty() {
+            Ok(None)
+        } else {
+            Url::parse(raw)
+                .map(Some)
+                .map_err(|_| FieldErrorKind::InvalidUrl)
+        }
+    }
+
+    fn to_raw(&self) -> String {
+        self.as_ref()
+            .map(|url| url.as_str().to_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Collects the errors while parsing the fields of a [`RawEntry`].
+#[derive(Default)]
+struct Parser {
+    errors: Vec<FieldError>,
+}
+
+impl Parser {
+    /// Parses a raw value, returning the default value of the type on error.
+    ///
+    /// A value may be converted back to a different but equivalent raw value (i.e. `"03"` or
+    /// `"3.0"` become `"3"`, and a URL is normalized), but it must not lose any information.
+    fn parse<T: RawValue>(&mut self, field: ServerField, raw: String) -> T {
+        T::from_raw(&raw).unwrap_or_else(|kind| {
+            self.errors.push(FieldError {
+                field,
+                value: raw,
+                kind,
+            });
+            T::default()
+        })
+    }
+}
+
+impl TryFrom<RawEntry> for Entry {
+    type Error = EntryError;
+
+    fn try_from(raw: RawEntry) -> Result<Self, Self::Error> {
+        let RawEntry {
+            id,
+            geo_point_2d,
+            geo_shape,
+            name,
+            etichetta,
+            notetesto,
+            numeroantico,
+            numeromoderno,
+            link1,
+            link2,
+            link3,
+            piani,
+            arcate,
+            architravate,
+            architravate_con_colonne_di_legno,
+            archivolti,
+            modiglioni,
+            mensoloni_architravati,
+            stalla_e,
+            fienile_i,
+            rimessa_e,
+            scuderia_e,
+            attivita_commerciali_produttive_1,
+            attivita_commerciali_produttive_2,
+            attivita_commerciali_produttive_3,
+            attivita_commerciali_produttive_4,
+            attivita_commerciali_produttive_5,
+        } = raw;
+
+        let mut parser = Parser::default();
+        let numeromoderno = parser.parse(ServerField::Numeromoderno, numeromoderno);
+        let link1 = parser.parse(ServerField::Link1, link1);
+        let link2 = parser.parse(ServerField::Link2, link2);
+        let link3 = parser.parse(ServerField::Link3, link3);
+        let piani = parser.parse(ServerField::Piani, piani);
+        let stalla_e = parser.parse(ServerField::StallaE, stalla_e);
+        let fienile_i = parser.parse(ServerField::FienileI, fienile_i);
+        let rimessa_e = parser.parse(ServerField::RimessaE, rimessa_e);
+        let scuderia_e = parser.parse(ServerField::ScuderiaE, scuderia_e);
+
+        if parser.errors.is_empty().not() {
+            return Err(EntryError(parser.errors));
+        }
+
+        Ok(Self {
+            id,
+            geo_point_2d,
+            geo_shape,
+            name,
+            etichetta,
+            notetesto,
+            numeroantico,
+            numeromoderno,
+            link1,
+            link2,
+            link3,
+            piani,
+            arcate,
+            architravate,
+            architravate_con_colonne_di_legno,
+            archivolti,
+            modiglioni,
+            mensoloni_architravati,
+            stalla_e,
+            fienile_i,
+            rimessa_e,
+            scuderia_e,
+            attivita_commerciali_produttive_1,
+            attivita_commerciali_produttive_2,
+            attivita_commerciali_produttive_3,
+            attivita_commerciali_produttive_4,
+            attivita_commerciali_produttive_5,
+        })
+    }
+}
+
+impl From<Entry> for RawEntry {
+    fn from(entry: Entry) -> Self {
+        let Entry {
+            id,
+            geo_point_2d,
+            geo_shape,
+            name,
+            etichetta,
+            notetesto,
+            numeroantico,
+            numeromoderno,
+            link1,
+            link2,
+            link3,
+            piani,
+            arcate,
+            architravate,
+            architravate_con_colonne_di_legno,
+            archivolti,
+            modiglioni,
+            mensoloni_architravati,
+            stalla_e,
+            fienile_i,
+            rimessa_e,
+            scuderia_e,
+            attivita_commerciali_produttive_1,
+            attivita_commerciali_produttive_2,
+            attivita_commerciali_produttive_3,
+            attivita_commerciali_produttive_4,
+            attivita_commerciali_produttive_5,
+        } = entry;
+
+        Self {
+            id,
+            geo_point_2d,
+            geo_shape,
+            name,
+            etichetta,
+            notetesto,
+            numeroantico,
+            numeromoderno: numeromoderno.to_raw(),
+            link1: link1.to_raw(),
+            link2: link2.to_raw(),
+            link3: link3.to_raw(),
+            piani: piani.to_raw(),
+            arcate,
+            architravate,
+            architravate_con_colonne_di_legno,
+            archivolti,
+            modiglioni,
+            mensoloni_architravati,
+            stalla_e: stalla_e.to_raw(),
+            fienile_i: fienile_i.to_raw(),
+            rimessa_e: rimessa_e.to_raw(),
+            scuderia_e: scuderia_e.to_raw(),
+            attivita_commerciali_produttive_1,
+            attivita_commerciali_produttive_2,
+            attivita_commerciali_produttive_3,
+            attivita_commerciali_produttive_4,
+            attivita_commerciali_produttive_5,
+        }
+    }
+}
+
+/// The error obtained when a [`RawEntry`] cannot be converted to an [`Entry`].
+///
+/// All the invalid fields are reported, not only the first one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntryError(pub Vec<FieldError>);
+
+impl Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid entry: ")?;
+
+        let mut errors = self.0.iter();
+        if let Some(error) = errors.next() {
+            write!(f, "{error}")?;
+        }
+        errors.try_for_each(|error| write!(f, "; {error}"))
+    }
+}
+
+impl std::error::Error for EntryError {}
+
+/// A field of a [`RawEntry`] that cannot be converted to its typed representation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FieldError {
+    /// The invalid field.
+    pub field: ServerField,
+
+    /// The raw value of the field.
+    pub value: String,
+
+    /// The reason of the error.
+    pub kind: FieldErrorKind,
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "field `{}` with value {:?}: {}",
+            self.field.to_str(),
+            self.value,
+            self.kind
+        )
+    }
+}
+
+/// The possible reasons for a [`FieldError`].
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum FieldErrorKind {
+    /// The value is not a valid number.
+    InvalidNumber,
+
+    /// The value is neither empty nor [`FLAG_SET`].
+    InvalidFlag,
+
+    /// The value is not a valid URL.
+    InvalidUrl,
+
+    /// The value is a number, but its fractional part would be lost.
+    NotLossless,
+}
+
+impl Display for FieldErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            FieldErrorKind::InvalidNumber => "invalid number",
+            FieldErrorKind::InvalidFlag => "invalid flag",
+            FieldErrorKind::InvalidUrl => "invalid URL",
+            FieldErrorKind::NotLossless => "value would lose its fractional part",
+        };
+
+        f.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::fixtures::raw_entry;
+
+    use super::*;
+
+    #[test]
+    fn lossless_conversion() {
+        let raw = raw_entry();
+        let entry = Entry::try_from(raw.clone()).unwrap();
+        assert_eq!(entry.numeromoderno, Some(33));
+        assert_eq!(entry.piani, Some(3));
+        assert!(entry.stalla_e);
+        assert!(entry.fienile_i.not());
+        assert_eq!(
+            entry.link1.as_ref().map(Url::as_str),
+            Some("https://example.com/photo.jpg")
+        );
+        assert_eq!(entry.link2, None);
+
+        assert_eq!(RawEntry::from(entry), raw);
+    }
+
+    #[test]
+    fn non_canonical_values() {
+        let raw = RawEntry {
+            numeromoderno: "033".to_owned(),
+            piani: "3.0".to_owned(),
+            link1: "https://example.com".to_owned(),
+            ..raw_entry()
+        };
+
+        let entry = Entry::try_from(raw).unwrap();
+        assert_eq!(entry.numeromoderno, Some(33));
+        assert_eq!(entry.piani, Some(3));
+        assert_eq!(
+            entry.link1.as_ref().map(Url::as_str),
+            Some("https://example.com/")
+        );
+
+        let raw = RawEntry::from(entry);
+        assert_eq!(raw.numeromoderno, "33");
+        assert_eq!(raw.piani, "3");
+    }
+
+    #[test]
+    fn invalid_fields_are_reported() {
+        let raw = RawEntry {
+            numeromoderno: "3.5".to_owned(),
+            link2: "not a link".to_owned(),
+            piani: "tre".to_owned(),
+            fienile_i: "si".to_owned(),
+            ..raw_entry()
+        };
+
+        let EntryError(errors) = Entry::try_from(raw).unwrap_err();
+        let errors: Vec<_> = errors
+            .into_iter()
+            .map(|error| (error.field, error.kind))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (ServerField::Numeromoderno, FieldErrorKind::NotLossless),
+                (ServerField::Link2, FieldErrorKind::InvalidUrl),
+                (ServerField::Piani, FieldErrorKind::InvalidNumber),
+                (ServerField::FienileI, FieldErrorKind::InvalidFlag),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_serde_uses_raw_format() {
+        let raw = raw_entry();
+        let json = serde_json::to_value(&raw).unwrap();
+
+        let entry: Entry = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&entry).unwrap(), json);
+    }
+}
@@ -7,12 +7,15 @@
 use std::{
     cell::Cell,
     fmt::{self, Display},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
+use serde::{Deserialize, Serialize};
 use tokio::time::Instant;
 
-use crate::{BUCKET_CAPACITY_HEADER, BUCKET_LEAK_PER_SECOND_HEADER, BUCKET_POINTS_HEADER};
+mod headers;
+
+pub use headers::{BucketInfo, FromHeaderError};
 
 /// A simple [leaky bucket] implementation.
 ///
@@ -130,6 +133,16 @@ impl LeakyBucket {
         points
     }
 
+    /// Removes some points from the bucket, saturating to zero.
+    ///
+    /// This is useful to give back the points of an operation which turned out to be cheaper than
+    /// expected. Returns the new amount of points in the bucket.
+    pub fn refund(&self, points: u16) -> u16 {
+        let points = self.points().saturating_sub(points);
+        self.last_points.set(points);
+        points
+    }
+
     /// Returns the number of available points.
     pub fn available(&self) -> u16 {
         self.capacity - self.points()
@@ -149,13 +162,64 @@ impl LeakyBucket {
                 let seconds_needed = to_restore / u16::from(self.leak_per_second)
                     + u16::from(to_restore % u16::from(self.leak_per_second) != 0);
 
-                Duration::from_secs(seconds_needed.into())
-                    - Duration::from_nanos(self.last_time.get().remainder_nanos.into())
+                Duration::from_secs(seconds_needed.into()).saturating_sub(Duration::from_nanos(
+                    self.last_time.get().remainder_nanos.into(),
+                ))
             }
         }
     }
 }
 
+/// A snapshot of the state of a [`LeakyBucket`], which can be persisted.
+///
+/// The wall-clock time is used instead of an [`Instant`], therefore the bucket can be restored by
+/// another process, for instance after a restart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub points: u16,
+    pub capacity: u16,
+    pub leak_per_second: u8,
+
+    /// The time from which the points leak, which includes the time passed since the beginning of
+    /// the _current second_.
+    pub taken_at: SystemTime,
+}
+
+impl LeakyBucket {
+    /// Takes a snapshot of the current state of the bucket.
+    #[must_use]
+    pub fn snapshot(&self) -> Snapshot {
+        let points = self.points();
+        let remainder = Duration::from_nanos(self.last_time.get().remainder_nanos.into());
+
+        Snapshot {
+            points,
+            capacity: self.capacity,
+            leak_per_second: self.leak_per_second,
+            taken_at: SystemTime::now() - remainder,
+        }
+    }
+
+    /// Restores a bucket from a snapshot, leaking the points for the time passed since then.
+    ///
+    /// Points exceeding the capacity are discarded. A clock that went backwards is handled as if no
+    /// time passed.
+    #[must_use]
+    pub fn restore(snapshot: Snapshot) -> Self {
+        let elapsed = snapshot.taken_at.elapsed().unwrap_or_default();
+        let elapsed_secs = u16::try_from(elapsed.as_secs()).unwrap_or(u16::MAX);
+        let leak = elapsed_secs.saturating_mul(u16::from(snapshot.leak_per_second));
+        let points = snapshot.points.saturating_sub(leak).min(snapshot.capacity);
+
+        let bucket = Self::with_points(points, snapshot.capacity, snapshot.leak_per_second);
+        bucket.last_time.set(LastTime {
+            instant: Instant::now(),
+            remainder_nanos: elapsed.subsec_nanos(),
+        });
+        bucket
+    }
+}
+
 /// An error representing an operation that would make the points exceed the capacity.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct MaxCapacityError(
@@ -169,79 +233,6 @@ impl Display for MaxCapacityError {
     }
 }
 
-impl TryFrom<&reqwest::header::HeaderMap> for LeakyBucket {
-    type Error = FromHeaderError;
-
-    fn try_from(headers: &reqwest::header::HeaderMap) -> Result<Self, Self::Error> {
-        let points = headers
-            .get(BUCKET_POINTS_HEADER)
-            .ok_or(FromHeaderError::NoPoints)?;
-        let capacity = headers
-            .get(BUCKET_CAPACITY_HEADER)
-            .ok_or(FromHeaderError::NoCapacity)?;
-        let leak_per_second = headers
-            .get(BUCKET_LEAK_PER_SECOND_HEADER)
-            .ok_or(FromHeaderError::NoLeakPerSecond)?;
-
-        let points = points
-            .to_str()
-            .ok()
-            .and_then(|points| points.parse().ok())
-            .ok_or(FromHeaderError::InvalidPoints)?;
-        let capacity = capacity
-            .to_str()
-            .ok()
-            .and_then(|capacity| capacity.parse().ok())
-            .ok_or(FromHeaderError::InvalidCapacity)?;
-        let leak_per_second = leak_per_second
-            .to_str()
-            .ok()
-            .and_then(|leak_per_second| leak_per_second.parse().ok())
-            .ok_or(FromHeaderError::InvalidLeakPerSecond)?;
-
-        Ok(Self::with_points(points, capacity, leak_per_second))
-    }
-}
-
-/// The possible errors when trying to convert a [`HeaderMap`] to a [`LeakyBucket`]
-///
-/// [`HeaderMap`]: `reqwest::header::HeaderMap`
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
-pub enum FromHeaderError {
-    /// Missing _points_ header
-    NoPoints,
-
-    /// Missing _capacity_ header
-    NoCapacity,
-
-    /// Missing _leak per second_ header
-    NoLeakPerSecond,
-
-    /// Invalid _points_ header
-    InvalidPoints,
-
-    /// Invalid _capacity_ header
-    InvalidCapacity,
-
-    /// Invalid _leak per second_ header
-    InvalidLeakPerSecond,
-}
-
-impl fmt::Display for FromHeaderError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            FromHeaderError::NoPoints => "no point header",
-            FromHeaderError::NoCapacity => "no capacity header",
-            FromHeaderError::NoLeakPerSecond => "no leak per second header",
-            FromHeaderError::InvalidPoints => "invalid point header",
-            FromHeaderError::InvalidCapacity => "invalid point capacity",
-            FromHeaderError::InvalidLeakPerSecond => "invalid leak per second header",
-        };
-
-        f.write_str(s)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use tokio::time::sleep;
@@ -284,10 +275,10 @@ mod tests {
         sleep(Duration::from_millis(500)).await;
         assert_eq!(bucket.points(), 3);
 
-        sleep(Duration::from_millis(2000)).await;
+        sleep(Duration::from_secs(2)).await;
         assert_eq!(bucket.points(), 1);
 
-        sleep(Duration::from_millis(2000)).await;
+        sleep(Duration::from_secs(2)).await;
         assert_eq!(bucket.points(), 0);
     }
 
@@ -314,4 +305,42 @@ mod tests {
         assert_eq!(bucket.saturating_add(4), 10);
         assert_eq!(bucket.points(), 10);
     }
+
+    #[test]
+    fn refund_points() {
+        let bucket = LeakyBucket::with_points(7, 10, 1);
+        assert_eq!(bucket.refund(4), 3);
+        assert_eq!(bucket.points(), 3);
+        assert_eq!(bucket.refund(4), 0);
+        assert_eq!(bucket.points(), 0);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let bucket = LeakyBucket::with_points(50, 100, 4);
+        let snapshot = bucket.snapshot();
+        assert_eq!(snapshot.points, 50);
+
+        let snapshot: Snapshot =
+            serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        let restored = LeakyBucket::restore(snapshot);
+        assert_eq!(restored.points(), 50);
+        assert_eq!(restored.capacity(), 100);
+        assert_eq!(restored.leak_per_second(), 4);
+
+        let snapshot = Snapshot {
+            taken_at: snapshot.taken_at - Duration::from_millis(10_500),
+            ..snapshot
+        };
+        let restored = LeakyBucket::restore(snapshot);
+        assert_eq!(restored.points(), 10);
+        assert!(restored.last_time.get().remainder_nanos >= 500_000_000);
+
+        let snapshot = Snapshot {
+            points: 200,
+            taken_at: SystemTime::now(),
+            ..snapshot
+        };
+        assert_eq!(LeakyBucket::restore(snapshot).points(), 100);
+    }
 }
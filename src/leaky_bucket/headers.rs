@@ -0,0 +1,370 @@
+#![warn(clippy::pedantic)]
+
+//! Encoding and decoding of the state of a [`LeakyBucket`] in HTTP headers.
+//!
+//! Both the `x-bucket-*` headers and the standard `RateLimit-*` ones are supported. The functions
+//! work on the types of the [`http`] crate, which are used by both [hyper] and [reqwest], or on
+//! plain strings for any other client.
+//!
+//! [hyper]: https://docs.rs/hyper
+//! [reqwest]: https://docs.rs/reqwest
+
+use std::{fmt, str::FromStr};
+
+use http::{header::HeaderName, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use super::LeakyBucket;
+use crate::{
+    BUCKET_CAPACITY_HEADER, BUCKET_LEAK_PER_SECOND_HEADER, BUCKET_POINTS_HEADER,
+    RATELIMIT_LIMIT_HEADER, RATELIMIT_POLICY_HEADER, RATELIMIT_REMAINING_HEADER,
+    RATELIMIT_RESET_HEADER,
+};
+
+/// The state of a [`LeakyBucket`] at a given time, as sent in the headers of a response.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BucketInfo {
+    pub points: u16,
+    pub capacity: u16,
+    pub leak_per_second: u8,
+}
+
+impl BucketInfo {
+    /// Returns the current state of the bucket.
+    #[must_use]
+    pub fn new(bucket: &LeakyBucket) -> Self {
+        Self {
+            points: bucket.points(),
+            capacity: bucket.capacity(),
+            leak_per_second: bucket.leak_per_second(),
+        }
+    }
+
+    /// Returns the names and the values of all the headers describing the bucket.
+    ///
+    /// Both the `x-bucket-*` headers and the standard `RateLimit-*` ones are included.
+    #[must_use]
+    pub fn header_values(&self) -> [(&'static str, String); 7] {
+        let leak_per_second = u16::from(self.leak_per_second).max(1);
+        let reset = self.points.div_ceil(leak_per_second);
+        let window = self.capacity.div_ceil(leak_per_second);
+
+        [
+            (BUCKET_POINTS_HEADER, self.points.to_string()),
+            (BUCKET_CAPACITY_HEADER, self.capacity.to_string()),
+            (
+                BUCKET_LEAK_PER_SECOND_HEADER,
+                self.leak_per_second.to_string(),
+            ),
+            (RATELIMIT_LIMIT_HEADER, self.capacity.to_string()),
+            (
+                RATELIMIT_REMAINING_HEADER,
+                self.capacity.saturating_sub(self.points).to_string(),
+            ),
+            (RATELIMIT_RESET_HEADER, reset.to_string()),
+            (
+                RATELIMIT_POLICY_HEADER,
+                format!(
+                    "{};w={window};leak-per-second={}",
+                    self.capacity, self.leak_per_second
+                ),
+            ),
+        ]
+    }
+
+    /// Adds the headers describing the bucket, replacing the existing ones.
+    ///
+    /// # Panics
+    ///
+    /// Never panics, all the header values are valid.
+    pub fn encode(&self, headers: &mut HeaderMap) {
+        for (name, value) in self.header_values() {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::try_from(value).expect("bucket headers should be valid"),
+            );
+        }
+    }
+
+    /// Reads the state of the bucket from the headers of a response.
+    ///
+    /// See [`BucketInfo::decode_with`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the headers are missing or invalid.
+    pub fn decode(headers: &HeaderMap) -> Result<Self, FromHeaderError> {
+        Self::decode_with(|name| headers.get(name).and_then(|value| value.to_str().ok()))
+    }
+
+    /// Reads the state of the bucket using a function returning the value of a header by name.
+    ///
+    /// The `x-bucket-*` headers are used if present, otherwise the standard `RateLimit-*` ones.
+    /// The function is called with lowercase names.
+    ///
+    /// Without the `leak-per-second` parameter of the policy, the leak per second is computed
+    /// from the window, which is rounded to whole seconds: in this case the result is rounded down
+    /// and it can be lower than the actual leak.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the headers are missing or invalid.
+    pub fn decode_with<'a>(
+        get: impl Fn(&'static str) -> Option<&'a str>,
+    ) -> Result<Self, FromHeaderError> {
+        if get(BUCKET_POINTS_HEADER).is_none() && get(RATELIMIT_LIMIT_HEADER).is_some() {
+            return Self::decode_ratelimit(get);
+        }
+
+        Ok(Self {
+            points: parse(
+                get(BUCKET_POINTS_HEADER),
+                FromHeaderError::NoPoints,
+                FromHeaderError::InvalidPoints,
+            )?,
+            capacity: parse(
+                get(BUCKET_CAPACITY_HEADER),
+                FromHeaderError::NoCapacity,
+                FromHeaderError::InvalidCapacity,
+            )?,
+            // A bucket that never leaks could never be used again once full.
+            leak_per_second: parse(
+                get(BUCKET_LEAK_PER_SECOND_HEADER),
+                FromHeaderError::NoLeakPerSecond,
+                FromHeaderError::InvalidLeakPerSecond,
+            )
+            .and_then(|leak_per_second: u8| {
+                (leak_per_second > 0)
+                    .then_some(leak_per_second)
+                    .ok_or(FromHeaderError::InvalidLeakPerSecond)
+            })?,
+        })
+    }
+
+    /// Reads the state of the bucket from the `RateLimit-Limit`, `RateLimit-Remaining` and
+    /// `RateLimit-Policy` headers.
+    fn decode_ratelimit<'a>(
+        get: impl Fn(&'static str) -> Option<&'a str>,
+    ) -> Result<Self, FromHeaderError> {
+        let capacity: u16 = parse(
+            get(RATELIMIT_LIMIT_HEADER),
+            FromHeaderError::NoCapacity,
+            FromHeaderError::InvalidCapacity,
+        )?;
+        let remaining: u16 = parse(
+            get(RATELIMIT_REMAINING_HEADER),
+            FromHeaderError::NoPoints,
+            FromHeaderError::InvalidPoints,
+        )?;
+        let points = capacity
+            .checked_sub(remaining)
+            .ok_or(FromHeaderError::InvalidPoints)?;
+
+        let leak_per_second =
+            parse_policy(get(RATELIMIT_POLICY_HEADER).ok_or(FromHeaderError::NoLeakPerSecond)?)
+                .ok_or(FromHeaderError::InvalidLeakPerSecond)?;
+
+        Ok(Self {
+            points,
+            capacity,
+            leak_per_second,
+        })
+    }
+}
+
+impl From<BucketInfo> for LeakyBucket {
+    /// Creates a bucket with the given state.
+    ///
+    /// Points exceeding the capacity are discarded.
+    fn from(info: BucketInfo) -> Self {
+        Self::with_points(
+            info.points.min(info.capacity),
+            info.capacity,
+            info.leak_per_second,
+        )
+    }
+}
+
+impl TryFrom<&HeaderMap> for LeakyBucket {
+    type Error = FromHeaderError;
+
+    /// Creates the bucket from the `x-bucket-*` headers or, if missing, from the standard
+    /// `RateLimit-*` headers.
+    fn try_from(headers: &HeaderMap) -> Result<Self, Self::Error> {
+        BucketInfo::decode(headers).map(Self::from)
+    }
+}
+
+/// Returns the leak per second described by the first `quota;w=window` policy.
+///
+/// The `leak-per-second` parameter is preferred, since the window could be rounded.
+fn parse_policy(policy: &str) -> Option<u8> {
+    let mut params = policy.split(',').next()?.split(';').map(str::trim);
+    let quota: u16 = params.next()?.parse().ok()?;
+    let params: Vec<_> = params.collect();
+    let param = |name: &str| {
+        params
+            .iter()
+            .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+    };
+
+    let leak_per_second = match param("leak-per-second") {
+        Some(leak_per_second) => leak_per_second.parse().ok()?,
+        None => quota.checked_div(param("w")?.parse().ok()?)?,
+    };
+    u8::try_from(leak_per_second)
+        .ok()
+        .filter(|&leak_per_second| leak_per_second > 0)
+}
+
+/// Parses the value of a header, returning the given errors if it is missing or invalid.
+fn parse<T: FromStr>(
+    value: Option<&str>,
+    missing: FromHeaderError,
+    invalid: FromHeaderError,
+) -> Result<T, FromHeaderError> {
+    value.ok_or(missing)?.trim().parse().map_err(|_| invalid)
+}
+
+/// The possible errors when trying to convert a [`HeaderMap`] to a [`LeakyBucket`]
+///
+/// With the standard `RateLimit-*` headers, the points come from `RateLimit-Remaining`, the
+/// capacity from `RateLimit-Limit` and the leak per second from `RateLimit-Policy`.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum FromHeaderError {
+    /// Missing _points_ header
+    NoPoints,
+
+    /// Missing _capacity_ header
+    NoCapacity,
+
+    /// Missing _leak per second_ header
+    NoLeakPerSecond,
+
+    /// Invalid _points_ header
+    InvalidPoints,
+
+    /// Invalid _capacity_ header
+    InvalidCapacity,
+
+    /// Invalid _leak per second_ header
+    InvalidLeakPerSecond,
+}
+
+impl fmt::Display for FromHeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            FromHeaderError::NoPoints => "no point header",
+            FromHeaderError::NoCapacity => "no capacity header",
+            FromHeaderError::NoLeakPerSecond => "no leak per second header",
+            FromHeaderError::InvalidPoints => "invalid point header",
+            FromHeaderError::InvalidCapacity => "invalid point capacity",
+            FromHeaderError::InvalidLeakPerSecond => "invalid leak per second header",
+        };
+
+        f.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_headers() {
+        let mut headers: HeaderMap = [
+            (BUCKET_POINTS_HEADER, "135"),
+            (BUCKET_CAPACITY_HEADER, "500"),
+            (BUCKET_LEAK_PER_SECOND_HEADER, "4"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+        .collect();
+        let bucket = LeakyBucket::try_from(&headers).unwrap();
+        assert_eq!(
+            (bucket.points(), bucket.capacity(), bucket.leak_per_second()),
+            (135, 500, 4)
+        );
+
+        headers.insert(BUCKET_LEAK_PER_SECOND_HEADER, HeaderValue::from_static("0"));
+        assert_eq!(
+            LeakyBucket::try_from(&headers).unwrap_err(),
+            FromHeaderError::InvalidLeakPerSecond
+        );
+
+        let mut headers: HeaderMap = [
+            (RATELIMIT_LIMIT_HEADER, "500"),
+            (RATELIMIT_REMAINING_HEADER, "365"),
+            (RATELIMIT_POLICY_HEADER, "500;w=125"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+        .collect();
+        let bucket = LeakyBucket::try_from(&headers).unwrap();
+        assert_eq!(
+            (bucket.points(), bucket.capacity(), bucket.leak_per_second()),
+            (135, 500, 4)
+        );
+
+        headers.insert(RATELIMIT_POLICY_HEADER, HeaderValue::from_static("500"));
+        assert_eq!(
+            LeakyBucket::try_from(&headers).unwrap_err(),
+            FromHeaderError::InvalidLeakPerSecond
+        );
+        headers.remove(RATELIMIT_POLICY_HEADER);
+        assert_eq!(
+            LeakyBucket::try_from(&headers).unwrap_err(),
+            FromHeaderError::NoLeakPerSecond
+        );
+        assert_eq!(
+            LeakyBucket::try_from(&HeaderMap::new()).unwrap_err(),
+            FromHeaderError::NoPoints
+        );
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let info = BucketInfo {
+            points: 135,
+            capacity: 500,
+            leak_per_second: 4,
+        };
+        let mut headers = HeaderMap::new();
+        info.encode(&mut headers);
+        assert_eq!(headers[RATELIMIT_REMAINING_HEADER], "365");
+        assert_eq!(headers[RATELIMIT_RESET_HEADER], "34");
+        assert_eq!(
+            headers[RATELIMIT_POLICY_HEADER],
+            "500;w=125;leak-per-second=4"
+        );
+        assert_eq!(BucketInfo::decode(&headers), Ok(info));
+
+        // Only the standard headers.
+        let values = info.header_values();
+        let decoded = BucketInfo::decode_with(|name| {
+            values
+                .iter()
+                .skip(3)
+                .find(|(value_name, _)| *value_name == name)
+                .map(|(_, value)| value.as_str())
+        });
+        assert_eq!(decoded, Ok(info));
+    }
+
+    #[test]
+    fn rounded_window() {
+        let info = BucketInfo {
+            points: 0,
+            capacity: 10,
+            leak_per_second: 3,
+        };
+        let values = info.header_values();
+        let policy = &values.last().unwrap().1;
+        assert_eq!(policy, "10;w=4;leak-per-second=3");
+        assert_eq!(parse_policy(policy), Some(3));
+
+        // Without the exact leak, it is computed from the rounded window.
+        assert_eq!(parse_policy("10;w=4"), Some(2));
+        assert_eq!(parse_policy("10;w=4;leak-per-second=x"), None);
+    }
+}
@@ -7,12 +7,51 @@
 //! possible implementation.
 //!
 //! [Rustlab]: https://rustlab.it/
+//!
+//! # Features
+//!
+//! Without any feature only the data model in [`database`] is compiled, which depends on serde,
+//! `serde_json` for the properties of the GeoJSON features and url for the links. The other parts
+//! are enabled by the following features, all of them enabled by default:
+//!
+//! - `macros`: the `projection!` procedural macro;
+//! - `rate-limit`: the [`LeakyBucket`] and its HTTP headers;
+//! - `client`: the [`EntryStream`] client, based on reqwest;
+//! - `geo`: the analysis, validation and simplification of the geographic shapes;
+//! - `server`: the example server, which can be embedded through [`server::Builder`].
+//!
+//! In order to use only the data model, disable the default features and enable `model-only`,
+//! which does not add anything but states the intent:
+//!
+//! ```toml
+//! workshop-rustlab-2022 = { version = "0.1.0", default-features = false, features = ["model-only"] }
+//! ```
+
+// Needed to make the code generated by the procedural macros work inside this crate.
+extern crate self as workshop_rustlab_2022;
 
+#[cfg(feature = "client")]
+pub mod client;
 pub mod database;
+#[cfg(any(feature = "client", feature = "server"))]
+mod fnv;
+#[cfg(feature = "rate-limit")]
 pub mod leaky_bucket;
+#[cfg(feature = "server")]
+pub mod server;
 
+#[cfg(feature = "client")]
+pub use client::EntryStream;
+#[cfg(feature = "rate-limit")]
 pub use leaky_bucket::LeakyBucket;
 
+#[doc(hidden)]
+pub mod __private {
+    //! Re-exports used by the code generated by the procedural macros.
+
+    pub use serde;
+}
+
 /// The HTTP header which represents leaky bucket points.
 pub const BUCKET_POINTS_HEADER: &str = "x-bucket-points";
 
@@ -21,3 +60,33 @@ pub const BUCKET_CAPACITY_HEADER: &str = "x-bucket-capacity";
 
 /// The HTTP header which represents leaky bucket leak-per-second.
 pub const BUCKET_LEAK_PER_SECOND_HEADER: &str = "x-bucket-leak-per-second";
+
+/// The HTTP header which represents the points refunded to the leaky bucket, when an operation
+/// costs less than its expected cost.
+///
+/// The points in the other headers already take the refund into account.
+pub const BUCKET_REFUND_HEADER: &str = "x-bucket-refund";
+
+/// The HTTP header which carries the API key of the client.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The standard HTTP header which represents the capacity of the leaky bucket.
+///
+/// See the [RateLimit header fields for HTTP] draft.
+///
+/// [RateLimit header fields for HTTP]: https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/
+pub const RATELIMIT_LIMIT_HEADER: &str = "ratelimit-limit";
+
+/// The standard HTTP header which represents the available points of the leaky bucket.
+pub const RATELIMIT_REMAINING_HEADER: &str = "ratelimit-remaining";
+
+/// The standard HTTP header which represents the seconds needed to empty the leaky bucket.
+pub const RATELIMIT_RESET_HEADER: &str = "ratelimit-reset";
+
+/// The standard HTTP header which represents the policy of the leaky bucket.
+///
+/// The policy is `capacity;w=window;leak-per-second=leak`.
+///
+/// The window is the time, in seconds, needed to empty a full bucket. It is rounded up, therefore
+/// the exact leak per second is sent in the `leak-per-second` extension parameter.
+pub const RATELIMIT_POLICY_HEADER: &str = "ratelimit-policy";
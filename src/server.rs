@@ -0,0 +1,1318 @@
+#![warn(clippy::pedantic)]
+
+//! The example server, which can be embedded in other applications and tests.
+//!
+//! The server is configured with a [`Builder`], which either returns an axum [`Router`] to be
+//! mounted in another service, or spawns the server returning a [`ServerHandle`].
+
+mod admin;
+mod buckets;
+mod chaos;
+mod database;
+mod error;
+mod etag;
+mod load;
+mod metrics;
+mod recording;
+mod store;
+mod tiers;
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    io,
+    net::SocketAddr,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use admin::AdminMessage;
+use axum::{
+    extract,
+    http::{
+        header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::from_fn,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+pub use buckets::BucketStatus;
+use buckets::{Buckets, ClientBucket, ClientId};
+use chaos::ChaosState;
+pub use chaos::{Chaos, Fault, Latency};
+use database::PartialEntry;
+use error::Error;
+use load::LoadState;
+pub use load::{Amount, Load, Profile, Sporadic};
+use metrics::Metrics;
+use rand::{rngs::StdRng, SeedableRng};
+pub use recording::Record;
+use recording::Recorder;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use serde_qs::axum::QsQuery;
+use store::{Store, WriteOperation, WriteOutcome};
+pub use tiers::{ApiKey, ApiKeys, Tier};
+use tiers::{Authenticator, Caller};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::{interval, MissedTickBehavior},
+};
+use tower_http::trace::TraceLayer;
+use tracing::warn;
+
+use crate::{
+    database::{
+        CostModel, Entry, EntryId, RawEntry, ScaledCost, ServerQuery, WeightedCost,
+        COST_MODEL_PATH, DEFAULT_PAGE_SIZE, LEAK_PER_SECOND, MAX_BUCKET_CAPACITY,
+    },
+    leaky_bucket::{BucketInfo, MaxCapacityError, Snapshot},
+    BUCKET_REFUND_HEADER,
+};
+
+struct AppStateInner {
+    sender: Sender<Message>,
+}
+
+type AppState = Arc<AppStateInner>;
+
+const BUFFER_SIZE: usize = 32;
+
+/// The content type of the Prometheus text format.
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The cost model shared by the server and the task handling the requests.
+type SharedCostModel = Arc<dyn CostModel + Send + Sync>;
+
+/// How often the load profiles are applied.
+const LOAD_TICK: Duration = Duration::from_millis(100);
+
+/// A builder of the example server.
+///
+/// Besides the entries, the server advertises its [`CostModel`] at [`COST_MODEL_PATH`] and exposes
+/// its metrics in the Prometheus text format at `/metrics`.
+///
+/// Each client has its own bucket, identified by its API key or by its IP address when the server
+/// is started with [`Builder::spawn`]. Otherwise, all the anonymous clients share the same bucket.
+///
+/// By default no API key is accepted and the buckets have a capacity of [`MAX_BUCKET_CAPACITY`]
+/// points and leak [`LEAK_PER_SECOND`] points per second, the changes to the entries are kept in
+/// memory only and the [`Load::default`] of other clients is simulated, with a random seed. No
+/// [`Chaos`] is injected, the requests are not recorded, the admin API is disabled and the costs
+/// follow the default [`WeightedCost`].
+#[derive(Debug)]
+pub struct Builder {
+    dataset: Vec<RawEntry>,
+    capacity: u16,
+    leak_per_second: u8,
+    load: Load,
+    seed: Option<u64>,
+    wal_path: Option<PathBuf>,
+    bucket_path: Option<PathBuf>,
+    chaos: Option<Chaos>,
+    recording_path: Option<PathBuf>,
+    admin_token: Option<String>,
+    api_keys: Option<ApiKeys>,
+    /// The cost model, with its JSON advertised to the clients.
+    cost_model: Option<(SharedCostModel, Value)>,
+}
+
+impl Builder {
+    /// Creates a builder of a server serving the given entries.
+    ///
+    /// The identifiers of the entries are assigned following their order. Entries which cannot be
+    /// converted to an [`Entry`] are served anyway, but a warning is logged.
+    #[must_use]
+    pub fn new(dataset: Vec<RawEntry>) -> Self {
+        Self {
+            dataset,
+            capacity: MAX_BUCKET_CAPACITY,
+            leak_per_second: LEAK_PER_SECOND,
+            load: Load::default(),
+            seed: None,
+            wal_path: None,
+            bucket_path: None,
+            chaos: None,
+            recording_path: None,
+            admin_token: None,
+            api_keys: None,
+            cost_model: None,
+        }
+    }
+
+    /// Sets the capacity and the leak per second of the buckets.
+    ///
+    /// Ignored if [`Builder::with_api_keys`] is used, the buckets following the tiers instead.
+    #[must_use]
+    pub fn with_bucket(mut self, capacity: u16, leak_per_second: u8) -> Self {
+        self.capacity = capacity;
+        self.leak_per_second = leak_per_second;
+        self
+    }
+
+    /// Sets the simulated load of other clients.
+    ///
+    /// Use [`Load::none`] in order to have the whole bucket available to the actual clients.
+    #[must_use]
+    pub fn with_load(mut self, load: Load) -> Self {
+        self.load = load;
+        self
+    }
+
+    /// Sets the seed used to add sporadic points to the buckets, making the load reproducible.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Persists the changes to the entries in the write-ahead log at the given path.
+    ///
+    /// The log is replayed when the server is built, and compacted to the differences from the
+    /// dataset. It grows with every change until the next start.
+    ///
+    /// The log is refused if the dataset changed. A new dataset must store the identifiers of the
+    /// existing entries, as exported by the server, and include the changes of the log, which is
+    /// then removed.
+    #[must_use]
+    pub fn with_wal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.wal_path = Some(path.into());
+        self
+    }
+
+    /// Restores the buckets from the snapshot at the given path, and saves them there when the
+    /// server stops.
+    #[must_use]
+    pub fn with_bucket_snapshot(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bucket_path = Some(path.into());
+        self
+    }
+
+    /// Injects faults in the responses, in order to test the resilience of the clients.
+    #[must_use]
+    pub fn with_chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Appends a [`Record`] of each request to the file at the given path, one JSON per line.
+    ///
+    /// The requests are recorded as seen by the clients, including the injected [`Chaos`].
+    #[must_use]
+    pub fn with_recording(mut self, path: impl Into<PathBuf>) -> Self {
+        self.recording_path = Some(path.into());
+        self
+    }
+
+    /// Enables the admin API under `/admin`, which requires the given bearer token.
+    ///
+    /// The API inspects and changes the buckets of the clients at runtime, see [`BucketStatus`].
+    /// The changes are not persisted, except for the points.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the token is empty.
+    #[must_use]
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        let token = token.into();
+        assert!(
+            token.is_empty().not(),
+            "the admin token should not be empty"
+        );
+        self.admin_token = Some(token);
+        self
+    }
+
+    /// Accepts the given API keys, sent in the [`API_KEY_HEADER`] header, each one assigning a
+    /// [`Tier`] to its client.
+    ///
+    /// The requests without a key belong to the anonymous tier, the ones with an unknown key are
+    /// rejected with `401 Unauthorized`. The requests exceeding the restrictions of the tier are
+    /// rejected with `403 Forbidden`, without being charged: in particular, only the tiers with
+    /// [`Tier::can_write`] can change the entries. Without API keys, every client can change them.
+    ///
+    /// [`API_KEY_HEADER`]: crate::API_KEY_HEADER
+    #[must_use]
+    pub fn with_api_keys(mut self, api_keys: ApiKeys) -> Self {
+        self.api_keys = Some(api_keys);
+        self
+    }
+
+    /// Charges the operations following the given cost model, which is advertised to the clients
+    /// as JSON.
+    ///
+    /// Each client is charged following the model scaled by the [`Tier::cost_multiplier`] of its
+    /// tier, which is the [`ScaledCost`] advertised to it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the model cannot be serialized as a JSON object.
+    #[must_use]
+    pub fn with_cost_model<M>(mut self, cost_model: M) -> Self
+    where
+        M: CostModel + Serialize + Send + Sync + 'static,
+    {
+        let json =
+            serde_json::to_value(&cost_model).expect("the cost model should be serializable");
+        assert!(
+            json.is_object(),
+            "the cost model should be serialized as a JSON object"
+        );
+        self.cost_model = Some((Arc::new(cost_model), json));
+        self
+    }
+
+    /// Creates the router of the server.
+    ///
+    /// The requests are handled by a task spawned on the current tokio runtime, which stops once
+    /// the router and all its clones are dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the write-ahead log or the recording cannot be opened, or the API keys
+    /// are not valid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn into_router(self) -> io::Result<Router> {
+        self.start().map(|(router, _handler)| router)
+    }
+
+    /// Spawns the server listening on the given address, on the current tokio runtime.
+    ///
+    /// Use port 0 in order to bind an ephemeral port, see [`ServerHandle::local_addr`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the write-ahead log or the recording cannot be opened, the API keys are
+    /// not valid or the address cannot be bound.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn(self, addr: SocketAddr) -> io::Result<ServerHandle> {
+        let (router, handler) = self.start()?;
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+
+        let server = axum::Server::try_bind(&addr)
+            .map_err(io::Error::other)?
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+        let local_addr = server.local_addr();
+
+        // The server owns the router, therefore the handler stops after the server.
+        let server = tokio::spawn(async move {
+            server
+                .with_graceful_shutdown(async {
+                    // A dropped handle stops the server as well.
+                    let _ = shutdown_receiver.await;
+                })
+                .await
+                .map_err(io::Error::other)
+        });
+
+        Ok(ServerHandle {
+            local_addr,
+            shutdown,
+            server,
+            handler,
+        })
+    }
+
+    /// Creates the router and spawns the task handling the requests.
+    fn start(self) -> io::Result<(Router, JoinHandle<()>)> {
+        let Self {
+            dataset,
+            capacity,
+            leak_per_second,
+            load,
+            seed,
+            wal_path,
+            bucket_path,
+            chaos,
+            recording_path,
+            admin_token,
+            api_keys,
+            cost_model,
+        } = self;
+
+        for (index, entry) in dataset.iter().enumerate() {
+            if let Err(error) = Entry::try_from(entry.clone()) {
+                warn!("database entry {index}: {error}");
+            }
+        }
+
+        let (cost_model, cost_model_json) = cost_model.unwrap_or_else(default_cost_model);
+        let api_keys = api_keys
+            .unwrap_or_else(|| ApiKeys::new(Tier::new(capacity, leak_per_second).with_writes()));
+        let authenticator = Arc::new(Authenticator::new(api_keys)?);
+
+        let store = match wal_path {
+            Some(wal_path) => Store::open(dataset, &wal_path)?,
+            None => Store::in_memory(dataset)?,
+        };
+        let recorder = recording_path
+            .map(|path| Recorder::open(&path))
+            .transpose()?;
+        let snapshots = bucket_path.as_deref().map(load_buckets).unwrap_or_default();
+        let buckets = Buckets::new(Arc::clone(authenticator.anonymous()), snapshots);
+        let rng = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+        let load = LoadState::new(load, rng);
+        let metrics = Arc::new(Metrics::new());
+        metrics.record_buckets(&buckets);
+
+        let (sender, receiver) = channel(BUFFER_SIZE);
+        let app_state = Arc::new(AppStateInner {
+            sender: sender.clone(),
+        });
+
+        let mut router = Router::new()
+            .route("/", get(root))
+            .route(
+                &format!("/{COST_MODEL_PATH}"),
+                get(move |caller: Caller| {
+                    let json = advertised_cost_model(&cost_model_json, &caller.tier);
+                    async move { Json(json) }
+                }),
+            )
+            .route("/entries", post(create_entry))
+            .route(
+                "/entries/:id",
+                get(get_entry)
+                    .put(replace_entry)
+                    .patch(patch_entry)
+                    .delete(delete_entry),
+            )
+            .layer(Extension(Arc::clone(&app_state)))
+            .layer(Extension(authenticator));
+        if let Some(chaos) = chaos {
+            let chaos = Arc::new(ChaosState::new(&chaos));
+            router = router.layer(from_fn(move |request, next| {
+                chaos::inject(Arc::clone(&chaos), request, next)
+            }));
+        }
+        if let Some(recorder) = recorder {
+            let recorder = Arc::new(recorder);
+            router = router.layer(from_fn(move |request, next| {
+                recording::record(Arc::clone(&recorder), request, next)
+            }));
+        }
+
+        // The metrics and the admin API are not affected by the chaos, and their requests are not
+        // measured.
+        let measured_metrics = Arc::clone(&metrics);
+        let scraped_metrics = Arc::clone(&metrics);
+        router = router.layer(from_fn(move |request, next| {
+            metrics::measure(Arc::clone(&measured_metrics), request, next)
+        }));
+        if let Some(admin_token) = admin_token {
+            router = router.merge(admin::router(app_state, admin_token));
+        }
+        let router = router
+            .route(
+                "/metrics",
+                get(move || async move {
+                    let queue_depth = BUFFER_SIZE - sender.capacity();
+                    (
+                        [(CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+                        scraped_metrics.render(queue_depth),
+                    )
+                }),
+            )
+            .layer(TraceLayer::new_for_http());
+
+        let handler = tokio::spawn(async move {
+            let buckets = handler(store, buckets, load, &metrics, &*cost_model, receiver).await;
+            if let Some(bucket_path) = bucket_path {
+                if let Err(error) = save_buckets(&buckets, &bucket_path) {
+                    warn!("unable to save the buckets: {error}");
+                }
+            }
+        });
+
+        Ok((router, handler))
+    }
+}
+
+/// Returns the JSON of the cost model scaled for the clients of a tier, see [`ScaledCost`].
+fn advertised_cost_model(cost_model: &Value, tier: &Tier) -> Value {
+    serde_json::to_value(ScaledCost::new(cost_model, tier.cost_multiplier))
+        .expect("the cost model is a JSON object")
+}
+
+/// Returns the default cost model, with its JSON.
+fn default_cost_model() -> (SharedCostModel, Value) {
+    let json = serde_json::to_value(WeightedCost::default())
+        .expect("the default cost model should be serializable");
+    (Arc::new(WeightedCost::default()), json)
+}
+
+/// A handle to a server spawned by [`Builder::spawn`].
+///
+/// Dropping the handle stops the server, without waiting for it.
+#[derive(Debug)]
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    server: JoinHandle<io::Result<()>>,
+    handler: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server is listening on.
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops the server gracefully, waiting for the pending requests and for the buckets to be
+    /// saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server failed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server or the handler of the requests panicked.
+    pub async fn shutdown(self) -> io::Result<()> {
+        // The server could have already stopped because of an error.
+        let _ = self.shutdown.send(());
+
+        let result = self.server.await.expect("the server panicked");
+        self.handler.await.expect("the request handler panicked");
+        result
+    }
+}
+
+/// Loads the snapshots of the buckets saved by a previous run, by client.
+///
+/// The buckets are restored with the points leaked in the meantime, then they take the capacity
+/// and leak of their tier on the next request of their client. If the snapshots are missing or
+/// invalid, there are no buckets.
+fn load_buckets(path: &Path) -> BTreeMap<ClientId, Snapshot> {
+    let snapshots = match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).map_err(io::Error::from),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return BTreeMap::new(),
+        Err(error) => Err(error),
+    };
+
+    snapshots.unwrap_or_else(|error| {
+        warn!("ignoring the saved buckets: {error}");
+        BTreeMap::new()
+    })
+}
+
+/// Saves the snapshots of the buckets, through a temporary file.
+fn save_buckets(buckets: &Buckets, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, serde_json::to_vec(&buckets.snapshots())?)?;
+    fs::rename(temp_path, path)
+}
+
+async fn root(
+    caller: Caller,
+    headers: HeaderMap,
+    QsQuery(params): QsQuery<ServerQuery>,
+    Extension(state): Extension<AppState>,
+) -> Reply {
+    caller.tier.check_query(&params)?;
+
+    send_message(&state, |replier| Message::Query {
+        caller,
+        query: params,
+        if_none_match: if_none_match(&headers),
+        replier,
+    })
+    .await
+}
+
+async fn get_entry(
+    caller: Caller,
+    extract::Path(id): extract::Path<EntryId>,
+    headers: HeaderMap,
+    QsQuery(params): QsQuery<ServerQuery>,
+    Extension(state): Extension<AppState>,
+) -> Reply {
+    caller.tier.check_fields(&params.fields)?;
+
+    send_message(&state, |replier| Message::Get {
+        caller,
+        id,
+        query: params,
+        if_none_match: if_none_match(&headers),
+        replier,
+    })
+    .await
+}
+
+async fn create_entry(
+    caller: Caller,
+    Json(entry): Json<RawEntry>,
+    Extension(state): Extension<AppState>,
+) -> impl IntoResponse {
+    send_write(&state, caller, WriteOperation::Create(entry)).await
+}
+
+async fn replace_entry(
+    caller: Caller,
+    extract::Path(id): extract::Path<EntryId>,
+    Json(entry): Json<RawEntry>,
+    Extension(state): Extension<AppState>,
+) -> impl IntoResponse {
+    send_write(&state, caller, WriteOperation::Replace(id, entry)).await
+}
+
+async fn patch_entry(
+    caller: Caller,
+    extract::Path(id): extract::Path<EntryId>,
+    Json(fields): Json<Map<String, Value>>,
+    Extension(state): Extension<AppState>,
+) -> impl IntoResponse {
+    send_write(&state, caller, WriteOperation::Patch(id, fields)).await
+}
+
+async fn delete_entry(
+    caller: Caller,
+    extract::Path(id): extract::Path<EntryId>,
+    Extension(state): Extension<AppState>,
+) -> impl IntoResponse {
+    send_write(&state, caller, WriteOperation::Delete(id)).await
+}
+
+/// Returns the value of the `If-None-Match` header, if any.
+fn if_none_match(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+}
+
+async fn send_write(state: &AppState, caller: Caller, operation: WriteOperation) -> Reply {
+    caller.tier.check_write()?;
+
+    send_message(state, |replier| Message::Write {
+        caller,
+        operation: Box::new(operation),
+        replier,
+    })
+    .await
+}
+
+async fn send_message<T>(
+    state: &AppState,
+    message: impl FnOnce(oneshot::Sender<T>) -> Message,
+) -> T {
+    let (replier, receiver) = oneshot::channel();
+    state.sender.send(message(replier)).await.unwrap();
+
+    receiver.await.unwrap()
+}
+
+/// The headers describing the bucket and the response.
+type Reply = Result<(HeaderMap, Response), error::Error>;
+type Replier = oneshot::Sender<Reply>;
+
+#[derive(Debug)]
+enum Message {
+    Query {
+        caller: Caller,
+        query: ServerQuery,
+        if_none_match: Option<String>,
+        replier: Replier,
+    },
+    Get {
+        caller: Caller,
+        id: EntryId,
+        query: ServerQuery,
+        if_none_match: Option<String>,
+        replier: Replier,
+    },
+    Write {
+        caller: Caller,
+        operation: Box<WriteOperation>,
+        replier: Replier,
+    },
+    Admin(AdminMessage),
+}
+
+/// Handles the messages until all the senders are dropped, then returns the buckets.
+async fn handler(
+    mut store: Store,
+    mut buckets: Buckets,
+    mut load: LoadState,
+    metrics: &Metrics,
+    cost_model: &(dyn CostModel + Sync),
+    mut receiver: Receiver<Message>,
+) -> Buckets {
+    let mut load_tick = interval(LOAD_TICK);
+    load_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let message = select! {
+            message = receiver.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = load_tick.tick(), if load.has_profiles() => {
+                apply_profiles(&mut load, &buckets);
+                metrics.record_buckets(&buckets);
+                continue;
+            }
+        };
+
+        // The profiles are applied before each request as well, in order to be up to date.
+        if load.has_profiles() {
+            apply_profiles(&mut load, &buckets);
+        }
+        store.commit_persisted();
+
+        match message {
+            Message::Query {
+                caller,
+                query,
+                if_none_match,
+                replier,
+            } => {
+                let cost_model = ScaledCost::new(cost_model, caller.tier.cost_multiplier);
+                let bucket = client_bucket(&mut buckets, caller, &mut load, metrics);
+                let reply = read_page(
+                    &store,
+                    bucket,
+                    metrics,
+                    &cost_model,
+                    &query,
+                    if_none_match.as_deref(),
+                );
+                replier.send(reply).unwrap();
+            }
+            Message::Get {
+                caller,
+                id,
+                query,
+                if_none_match,
+                replier,
+            } => {
+                let cost_model = ScaledCost::new(cost_model, caller.tier.cost_multiplier);
+                let bucket = client_bucket(&mut buckets, caller, &mut load, metrics);
+                let reply = read_entry(
+                    &store,
+                    bucket,
+                    metrics,
+                    &cost_model,
+                    id,
+                    &query,
+                    if_none_match.as_deref(),
+                );
+                replier.send(reply).unwrap();
+            }
+            Message::Write {
+                caller,
+                operation,
+                replier,
+            } => {
+                let cost_model = ScaledCost::new(cost_model, caller.tier.cost_multiplier);
+                let bucket = client_bucket(&mut buckets, caller, &mut load, metrics);
+                let reply = write(&mut store, bucket, metrics, &cost_model, *operation);
+
+                // The other requests are handled while the operation reaches the disk.
+                tokio::spawn(async move {
+                    // The request could have been dropped in the meantime.
+                    let _ = replier.send(reply.await);
+                });
+            }
+            Message::Admin(message) => admin::handle(&mut buckets, message),
+        }
+
+        metrics.record_buckets(&buckets);
+    }
+
+    buckets
+}
+
+/// Reserves the expected cost of a page in the bucket, then builds the page and settles the cost.
+fn read_page(
+    store: &Store,
+    bucket: &ClientBucket,
+    metrics: &Metrics,
+    cost_model: &dyn CostModel,
+    query: &ServerQuery,
+    if_none_match: Option<&str>,
+) -> Reply {
+    let reserved = reserve(bucket, metrics, cost_model.query_cost(query))?;
+
+    let entries: Vec<_> = store
+        .page(
+            query.page.unwrap_or(0),
+            query.page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+        )
+        .map(|entry| PartialEntry::from_query(entry, query))
+        .collect();
+    Ok(json_reply(
+        bucket,
+        metrics,
+        cost_model,
+        reserved,
+        &entries,
+        |bytes| cost_model.response_cost(query, entries.len(), bytes),
+        if_none_match,
+    ))
+}
+
+/// Reserves the expected cost of an entry in the bucket, then reads the entry and settles the
+/// cost.
+fn read_entry(
+    store: &Store,
+    bucket: &ClientBucket,
+    metrics: &Metrics,
+    cost_model: &dyn CostModel,
+    id: EntryId,
+    query: &ServerQuery,
+    if_none_match: Option<&str>,
+) -> Reply {
+    // The cost is the one of a page containing only the requested entry.
+    let page_query = ServerQuery {
+        page_size: Some(1),
+        ..query.clone()
+    };
+    let reserved = reserve(bucket, metrics, cost_model.query_cost(&page_query))?;
+
+    match store.get(id) {
+        Ok(entry) => Ok(json_reply(
+            bucket,
+            metrics,
+            cost_model,
+            reserved,
+            &PartialEntry::from_query(entry, query),
+            |bytes| cost_model.response_cost(&page_query, 1, bytes),
+            if_none_match,
+        )),
+        Err(error) => {
+            let cost = cost_model.response_cost(&page_query, 0, 0);
+            let bucket_headers = settle(bucket, metrics, reserved, cost);
+            Ok((bucket_headers, error.into_response()))
+        }
+    }
+}
+
+/// Charges the bucket for a write operation, then applies it. The cost is refunded if the
+/// operation is rejected.
+///
+/// Returns the reply, which is ready once the operation is persisted.
+fn write(
+    store: &mut Store,
+    bucket: &ClientBucket,
+    metrics: &Metrics,
+    cost_model: &dyn CostModel,
+    operation: WriteOperation,
+) -> impl Future<Output = Reply> + Send + 'static {
+    let cost = cost_model.write_cost();
+    let applied = reserve(bucket, metrics, cost).map(|reserved| match store.apply(operation) {
+        Ok(applied) => (settle(bucket, metrics, reserved, cost), Ok(applied)),
+        // The operation has not been performed.
+        Err(error) => (settle(bucket, metrics, reserved, 0), Err(error)),
+    });
+
+    async move {
+        let (bucket_headers, applied) = applied?;
+        let response = match applied {
+            Ok((outcome, persisted)) => match persisted.wait().await {
+                Ok(()) => match outcome {
+                    WriteOutcome::Created(id) => {
+                        (StatusCode::CREATED, Json(json!({ "id": id }))).into_response()
+                    }
+                    WriteOutcome::Updated | WriteOutcome::Deleted => {
+                        StatusCode::NO_CONTENT.into_response()
+                    }
+                },
+                Err(error) => error.into_response(),
+            },
+            Err(error) => error.into_response(),
+        };
+        Ok((bucket_headers, response))
+    }
+}
+
+/// Adds the points of the load profiles to all the buckets.
+fn apply_profiles(load: &mut LoadState, buckets: &Buckets) {
+    let points = load.apply_profiles();
+    if points > 0 {
+        for (_, bucket) in buckets.iter() {
+            bucket.bucket.saturating_add(points);
+        }
+    }
+}
+
+/// Returns the bucket of the client of a request, after adding the sporadic load.
+fn client_bucket<'a>(
+    buckets: &'a mut Buckets,
+    caller: Caller,
+    load: &mut LoadState,
+    metrics: &Metrics,
+) -> &'a ClientBucket {
+    let bucket = buckets.get_or_insert(caller.client, &caller.tier);
+    metrics.record_sporadic_points(load.apply_sporadic(&bucket.bucket));
+    bucket
+}
+
+/// Creates the JSON response of a query, settling the points reserved for it.
+///
+/// The actual cost is computed from the size of the body. The response carries an `ETag`: if it
+/// matches `if_none_match`, only the cost of a `304 Not Modified` response is charged and the body
+/// is omitted.
+fn json_reply(
+    bucket: &ClientBucket,
+    metrics: &Metrics,
+    cost_model: &dyn CostModel,
+    reserved: u16,
+    body: &impl Serialize,
+    response_cost: impl FnOnce(usize) -> u16,
+    if_none_match: Option<&str>,
+) -> (HeaderMap, Response) {
+    let body = serde_json::to_vec(body).expect("entries should always be serializable");
+    let etag = etag::from_body(&body);
+    let cost = response_cost(body.len());
+
+    if if_none_match.is_some_and(|if_none_match| etag::matches(if_none_match, &etag)) {
+        let cost = cost_model.not_modified_cost(cost);
+        let bucket_headers = settle(bucket, metrics, reserved, cost);
+        let response = (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+        return (bucket_headers, response);
+    }
+
+    let bucket_headers = settle(bucket, metrics, reserved, cost);
+    let headers = [
+        (CONTENT_TYPE, HeaderValue::from_static("application/json")),
+        (
+            ETAG,
+            HeaderValue::try_from(etag).expect("ETags are valid header values"),
+        ),
+    ];
+    (bucket_headers, (headers, body).into_response())
+}
+
+/// Reserves the expected cost of an operation in the bucket, unless the client is exempt.
+///
+/// Returns the reserved points, which must be settled with [`settle`] once the actual cost is
+/// known.
+fn reserve(bucket: &ClientBucket, metrics: &Metrics, cost: u16) -> Result<u16, Error> {
+    if bucket.is_exempt() {
+        return Ok(0);
+    }
+
+    let bucket = &bucket.bucket;
+    bucket
+        .add(cost)
+        .map(|_| cost)
+        .map_err(|MaxCapacityError(points)| {
+            metrics.record_cost(cost);
+
+            let capacity = bucket.capacity();
+            Error::NotEnoughCapacity {
+                request: cost,
+                points,
+                capacity,
+                leak_per_second: bucket.leak_per_second(),
+                // A request larger than the capacity can never be performed.
+                retry_after: (cost <= capacity).then(|| bucket.wait_time_to_use(cost)),
+            }
+        })
+}
+
+/// Settles the points reserved for an operation with its actual cost: the unused points are
+/// refunded, while the missing ones are added anyway.
+///
+/// Returns the headers describing the bucket after the operation, with the refunded points if
+/// any.
+fn settle(bucket: &ClientBucket, metrics: &Metrics, reserved: u16, cost: u16) -> HeaderMap {
+    metrics.record_cost(cost);
+
+    let mut headers = HeaderMap::new();
+    if bucket.is_exempt().not() {
+        match reserved.checked_sub(cost) {
+            Some(0) => {}
+            Some(refund) => {
+                bucket.bucket.refund(refund);
+                headers.insert(BUCKET_REFUND_HEADER, HeaderValue::from(refund));
+            }
+            None => {
+                bucket.bucket.saturating_add(cost - reserved);
+            }
+        }
+    }
+
+    BucketInfo::new(&bucket.bucket).encode(&mut headers);
+    headers
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::{env, process};
+
+    use futures_util::StreamExt;
+    use url::Url;
+
+    use super::*;
+    use crate::{
+        client::fetch_cost_model,
+        database::{fixtures, ServerField, WRITE_COST},
+        EntryStream, API_KEY_HEADER, BUCKET_REFUND_HEADER,
+    };
+
+    #[tokio::test]
+    async fn spawn_and_shutdown() {
+        let dataset = fixtures::dataset(3);
+        let bucket_path = env::temp_dir().join(format!("server-test-{}.json", process::id()));
+
+        let server = Builder::new(dataset.clone())
+            .with_bucket(1000, 10)
+            .with_seed(42)
+            .with_bucket_snapshot(&bucket_path)
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        assert_ne!(server.local_addr().port(), 0);
+
+        let url = Url::parse(&format!("http://{}/", server.local_addr())).unwrap();
+        let entries: Vec<_> =
+            EntryStream::<RawEntry>::new(reqwest::Client::new(), url, ServerQuery::default())
+                .map(Result::unwrap)
+                .collect()
+                .await;
+        assert_eq!(entries.len(), dataset.len());
+        assert_eq!(entries[0].id, Some(EntryId(0)));
+        assert_eq!(entries[0].name, dataset[0].name);
+
+        server.shutdown().await.unwrap();
+        let snapshots: BTreeMap<String, Snapshot> =
+            serde_json::from_slice(&fs::read(&bucket_path).unwrap()).unwrap();
+        let snapshot = snapshots["127.0.0.1"];
+        assert_eq!(snapshot.capacity, 1000);
+        assert!(snapshot.points > 0);
+        fs::remove_file(bucket_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_entries() {
+        let wal_path = env::temp_dir().join(format!("server-wal-test-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&wal_path);
+        let spawn = || {
+            Builder::new(fixtures::dataset(2))
+                .with_load(Load::none())
+                .with_wal(&wal_path)
+                .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .unwrap()
+        };
+        let client = reqwest::Client::new();
+
+        let server = spawn();
+        let url = format!("http://{}/entries", server.local_addr());
+        let entry = serde_json::to_vec(&fixtures::dataset(1)[0]).unwrap();
+        let response = client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(entry)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let bucket = BucketInfo::decode(response.headers()).unwrap();
+        assert_eq!(bucket.points, WRITE_COST);
+        let body: Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(body["id"], 2);
+
+        let response = client
+            .patch(format!("{url}/2"))
+            .header(CONTENT_TYPE, "application/json")
+            .body(r#"{ "name": "Patched" }"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = client.delete(format!("{url}/0")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let bucket = BucketInfo::decode(response.headers()).unwrap();
+        assert!(bucket.points > 2 * WRITE_COST);
+
+        // The cost of a rejected operation is refunded.
+        let response = client.delete(format!("{url}/0")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[BUCKET_REFUND_HEADER],
+            WRITE_COST.to_string()
+        );
+        server.shutdown().await.unwrap();
+
+        // The changes are replayed after a restart.
+        let server = spawn();
+        let url = format!("http://{}/entries", server.local_addr());
+        let response = client.get(format!("{url}/0")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = client
+            .get(format!("{url}/2"))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let entry: RawEntry = serde_json::from_slice(&body).unwrap();
+        assert_eq!(entry.name, "Patched");
+        server.shutdown().await.unwrap();
+        fs::remove_file(wal_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn admin_api() {
+        let dataset = fixtures::dataset(3);
+        let cost = WeightedCost::default().response_cost(&ServerQuery::default(), dataset.len(), 0);
+        let server = Builder::new(dataset)
+            .with_load(Load::none())
+            .with_admin_token("secret")
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let url = format!("http://{}", server.local_addr());
+        let client = reqwest::Client::new();
+        let admin = |method, path: &str| {
+            client
+                .request(method, format!("{url}/admin/buckets{path}"))
+                .bearer_auth("secret")
+        };
+        let status = |response: reqwest::Response| async move {
+            let body = response.error_for_status().unwrap().bytes().await.unwrap();
+            serde_json::from_slice::<BucketStatus>(&body).unwrap()
+        };
+
+        let response = client
+            .get(format!("{url}/admin/buckets"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = admin(reqwest::Method::GET, "/127.0.0.1")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // A full page is reserved, then only the entries of the dataset are charged.
+        client.get(format!("{url}/")).send().await.unwrap();
+        let bucket = status(
+            admin(reqwest::Method::GET, "/127.0.0.1")
+                .send()
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(bucket.client, "127.0.0.1");
+        assert!(bucket.bucket.points <= cost);
+        assert!(bucket.bucket.points + u16::from(LEAK_PER_SECOND) > cost);
+
+        // The points exceeding the new capacity are discarded.
+        let capacity = cost / 2;
+        let bucket = status(
+            admin(reqwest::Method::PATCH, "/127.0.0.1")
+                .header(CONTENT_TYPE, "application/json")
+                .body(format!(r#"{{ "capacity": {capacity} }}"#))
+                .send()
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(
+            (bucket.bucket.points, bucket.bucket.capacity),
+            (capacity, capacity)
+        );
+        let response = client.get(format!("{url}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let bucket = status(
+            admin(reqwest::Method::POST, "/127.0.0.1/exempt")
+                .header(CONTENT_TYPE, "application/json")
+                .body(r#"{ "duration": 60 }"#)
+                .send()
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert!(bucket.exempt_for.is_some());
+        let response = client.get(format!("{url}/")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let bucket = status(
+            admin(reqwest::Method::POST, "/127.0.0.1/drain")
+                .send()
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(bucket.bucket.points, 0);
+
+        let bucket = status(
+            admin(reqwest::Method::POST, "/127.0.0.1/reset")
+                .send()
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(bucket.bucket.capacity, MAX_BUCKET_CAPACITY);
+        assert_eq!(bucket.exempt_for, None);
+
+        let body = admin(reqwest::Method::GET, "")
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let buckets: Vec<BucketStatus> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(buckets, [bucket]);
+
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn api_keys() {
+        let dataset = fixtures::dataset(3);
+        let anonymous = Tier::new(100, 1)
+            .with_allowed_fields([ServerField::Id, ServerField::Name])
+            .with_max_page_size(2);
+        let premium = Tier::new(5000, 50).with_cost_multiplier(0.5).with_writes();
+        let api_keys = ApiKeys::new(anonymous.clone())
+            .with_tier("premium", premium.clone())
+            .with_key("secret", "acme", "premium");
+        let server = Builder::new(dataset.clone())
+            .with_load(Load::none())
+            .with_api_keys(api_keys)
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let url = format!("http://{}", server.local_addr());
+        let client = reqwest::Client::new();
+        let get = |query: &str| client.get(format!("{url}/?{query}"));
+
+        let response = get("").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = get("page_size=3&fields[0]=name").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = get("page_size=2&fields[0]=name").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bucket = BucketInfo::decode(response.headers()).unwrap();
+        let query = ServerQuery {
+            fields: [ServerField::Name].into(),
+            page_size: Some(2),
+            ..ServerQuery::default()
+        };
+        let cost = WeightedCost::default().response_cost(&query, 2, 0);
+        assert_eq!((bucket.points, bucket.capacity), (cost, anonymous.capacity));
+
+        // The entries of the dataset cost less in the premium tier.
+        let response = get("")
+            .header(API_KEY_HEADER, "secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bucket = BucketInfo::decode(response.headers()).unwrap();
+        let cost = ScaledCost::new(WeightedCost::default(), premium.cost_multiplier).response_cost(
+            &ServerQuery::default(),
+            dataset.len(),
+            0,
+        );
+        assert_eq!((bucket.points, bucket.capacity), (cost, premium.capacity));
+
+        let response = get("")
+            .header(API_KEY_HEADER, "wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Only the premium tier can change the entries.
+        let delete = || client.delete(format!("{url}/entries/0"));
+        let response = delete().send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = delete()
+            .header(API_KEY_HEADER, "secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        server.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cost_model() {
+        let dataset = fixtures::dataset(3);
+        let cost_model = WeightedCost::default().with_weight(ServerField::GeoShape, 10.);
+        let api_keys = ApiKeys::new(Tier::new(MAX_BUCKET_CAPACITY, LEAK_PER_SECOND))
+            .with_tier("double", Tier::new(5000, 50).with_cost_multiplier(2.))
+            .with_key("secret", "acme", "double");
+        let server = Builder::new(dataset.clone())
+            .with_load(Load::none())
+            .with_api_keys(api_keys)
+            .with_cost_model(cost_model.clone())
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let url = Url::parse(&format!("http://{}/", server.local_addr())).unwrap();
+        let points_and_refund = |response: reqwest::Response| {
+            let headers = response.headers();
+            let refund = headers
+                .get(BUCKET_REFUND_HEADER)
+                .map(|refund| refund.to_str().unwrap().parse::<u16>().unwrap());
+            (BucketInfo::decode(headers).unwrap().points, refund)
+        };
+
+        let anonymous = reqwest::Client::new();
+        let with_key = reqwest::Client::builder()
+            .default_headers(
+                [(API_KEY_HEADER.parse().unwrap(), "secret".parse().unwrap())]
+                    .into_iter()
+                    .collect(),
+            )
+            .build()
+            .unwrap();
+        for (client, cost_multiplier) in [(anonymous, 1.), (with_key, 2.)] {
+            // Each client receives the model it is charged with.
+            let advertised = fetch_cost_model(&client, &url).await.unwrap();
+            assert_eq!(
+                advertised,
+                ScaledCost::new(cost_model.clone(), cost_multiplier)
+            );
+
+            // A full page is reserved, then only the entries actually returned are charged, at
+            // least a point.
+            let query = ServerQuery::default();
+            let reserved = advertised.query_cost(&query);
+            let empty = advertised.response_cost(&query, 0, 0);
+            let charged = advertised.response_cost(&query, dataset.len(), 0);
+            assert!(charged < reserved);
+            let response = client
+                .get(url.join("?page=100").unwrap())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(points_and_refund(response), (empty, Some(reserved - empty)));
+            let response = client.get(url.clone()).send().await.unwrap();
+            let (points, refund) = points_and_refund(response);
+            assert_eq!(refund, Some(reserved - charged));
+            // The points of the empty page could have already leaked.
+            assert!((charged..=charged + empty).contains(&points));
+        }
+
+        server.shutdown().await.unwrap();
+    }
+}
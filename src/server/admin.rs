@@ -0,0 +1,372 @@
+#![warn(clippy::pedantic)]
+
+//! The admin API, which inspects and changes the buckets of the clients at runtime.
+//!
+//! The routes require an `Authorization: Bearer <token>` header with the token passed to
+//! [`Builder::with_admin_token`]. All of them answer with the [`BucketStatus`] of the affected
+//! buckets:
+//!
+//! - `GET /admin/buckets` lists the buckets;
+//! - `GET /admin/buckets/:client` returns the bucket of a client;
+//! - `PATCH /admin/buckets/:client` changes the `capacity` and/or the positive `leak_per_second`;
+//! - `POST /admin/buckets/:client/reset` replaces the bucket with a default empty one;
+//! - `POST /admin/buckets/:client/drain` removes all the points;
+//! - `POST /admin/buckets/:client/exempt` stops charging the client for `duration` seconds.
+//!
+//! [`Builder::with_admin_token`]: super::Builder::with_admin_token
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{header::AUTHORIZATION, Request},
+    middleware::{from_fn, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use tokio::sync::oneshot;
+
+use super::{
+    buckets::{BucketStatus, Buckets, ClientId},
+    error::Error,
+    send_message, AppState, Message,
+};
+
+/// The replier of the messages changing a bucket.
+type StatusReplier = oneshot::Sender<BucketStatus>;
+
+/// The replier of the messages changing a bucket with parameters which can be invalid.
+type ChangeReplier = oneshot::Sender<Result<BucketStatus, Error>>;
+
+/// The messages of the admin API, handled by the same task handling the requests.
+#[derive(Debug)]
+pub(super) enum AdminMessage {
+    ListBuckets {
+        replier: oneshot::Sender<Vec<BucketStatus>>,
+    },
+    GetBucket {
+        client: ClientId,
+        replier: oneshot::Sender<Option<BucketStatus>>,
+    },
+    ConfigureBucket {
+        client: ClientId,
+        capacity: Option<u16>,
+        leak_per_second: Option<u8>,
+        replier: ChangeReplier,
+    },
+    ResetBucket {
+        client: ClientId,
+        replier: StatusReplier,
+    },
+    DrainBucket {
+        client: ClientId,
+        replier: StatusReplier,
+    },
+    ExemptClient {
+        client: ClientId,
+        duration: Duration,
+        replier: ChangeReplier,
+    },
+}
+
+/// The changes to the parameters of a bucket.
+#[derive(Debug, Deserialize)]
+struct Configuration {
+    capacity: Option<u16>,
+    leak_per_second: Option<u8>,
+}
+
+/// The time in which a client is not charged.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct Exemption {
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    duration: Duration,
+}
+
+/// Creates the router of the admin API, accepting only the requests with the given token.
+pub(super) fn router(state: AppState, token: String) -> Router {
+    let token = Arc::new(token);
+
+    Router::new()
+        .route("/admin/buckets", get(list_buckets))
+        .route(
+            "/admin/buckets/:client",
+            get(get_bucket).patch(configure_bucket),
+        )
+        .route("/admin/buckets/:client/reset", post(reset_bucket))
+        .route("/admin/buckets/:client/drain", post(drain_bucket))
+        .route("/admin/buckets/:client/exempt", post(exempt_client))
+        .layer(Extension(state))
+        .layer(from_fn(move |request, next| {
+            authorize(Arc::clone(&token), request, next)
+        }))
+}
+
+/// Handles a message of the admin API.
+pub(super) fn handle(buckets: &mut Buckets, message: AdminMessage) {
+    match message {
+        AdminMessage::ListBuckets { replier } => replier.send(buckets.list()).unwrap(),
+        AdminMessage::GetBucket { client, replier } => {
+            replier.send(buckets.status(&client)).unwrap();
+        }
+        AdminMessage::ConfigureBucket {
+            client,
+            capacity,
+            leak_per_second,
+            replier,
+        } => {
+            let status = buckets.configure(&client, capacity, leak_per_second);
+            replier.send(status).unwrap();
+        }
+        AdminMessage::ResetBucket { client, replier } => {
+            replier.send(buckets.reset(&client)).unwrap();
+        }
+        AdminMessage::DrainBucket { client, replier } => {
+            replier.send(buckets.drain(&client)).unwrap();
+        }
+        AdminMessage::ExemptClient {
+            client,
+            duration,
+            replier,
+        } => replier.send(buckets.exempt(&client, duration)).unwrap(),
+    }
+}
+
+/// Rejects the requests without the admin token.
+async fn authorize(token: Arc<String>, request: Request<Body>, next: Next<Body>) -> Response {
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| constant_time_eq(value.as_bytes(), token.as_bytes()));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        Error::Unauthorized.into_response()
+    }
+}
+
+/// Compares two byte strings in a time depending only on their lengths, so that the time of a
+/// failed attempt does not tell how much of the token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+async fn list_buckets(Extension(state): Extension<AppState>) -> Json<Vec<BucketStatus>> {
+    Json(send_admin(&state, |replier| AdminMessage::ListBuckets { replier }).await)
+}
+
+async fn get_bucket(
+    Path(client): Path<String>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<BucketStatus>, Error> {
+    let client = ClientId(client);
+    send_admin(&state, |replier| AdminMessage::GetBucket {
+        client: client.clone(),
+        replier,
+    })
+    .await
+    .map(Json)
+    .ok_or(Error::BucketNotFound(client.0))
+}
+
+async fn configure_bucket(
+    Path(client): Path<String>,
+    Json(configuration): Json<Configuration>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<BucketStatus>, Error> {
+    send_admin(&state, |replier| AdminMessage::ConfigureBucket {
+        client: ClientId(client),
+        capacity: configuration.capacity,
+        leak_per_second: configuration.leak_per_second,
+        replier,
+    })
+    .await
+    .map(Json)
+}
+
+async fn reset_bucket(
+    Path(client): Path<String>,
+    Extension(state): Extension<AppState>,
+) -> Json<BucketStatus> {
+    Json(
+        send_admin(&state, |replier| AdminMessage::ResetBucket {
+            client: ClientId(client),
+            replier,
+        })
+        .await,
+    )
+}
+
+async fn drain_bucket(
+    Path(client): Path<String>,
+    Extension(state): Extension<AppState>,
+) -> Json<BucketStatus> {
+    Json(
+        send_admin(&state, |replier| AdminMessage::DrainBucket {
+            client: ClientId(client),
+            replier,
+        })
+        .await,
+    )
+}
+
+async fn exempt_client(
+    Path(client): Path<String>,
+    Json(exemption): Json<Exemption>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<BucketStatus>, Error> {
+    send_admin(&state, |replier| AdminMessage::ExemptClient {
+        client: ClientId(client),
+        duration: exemption.duration,
+        replier,
+    })
+    .await
+    .map(Json)
+}
+
+async fn send_admin<T>(
+    state: &AppState,
+    message: impl FnOnce(oneshot::Sender<T>) -> AdminMessage,
+) -> T {
+    send_message(state, |replier| Message::Admin(message(replier))).await
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::{net::SocketAddr, ops::Not};
+
+    use axum::http::{header::CONTENT_TYPE, StatusCode};
+
+    use super::*;
+    use crate::{
+        database::fixtures,
+        server::{Builder, Load},
+    };
+
+    #[test]
+    fn compare_tokens() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secreT").not());
+        assert!(constant_time_eq(b"secret", b"secret!").not());
+        assert!(constant_time_eq(b"secret", b"").not());
+    }
+
+    #[tokio::test]
+    async fn rejected_requests() {
+        let server = Builder::new(fixtures::dataset(1))
+            .with_load(Load::none())
+            .with_admin_token("secret")
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let url = format!("http://{}/admin/buckets", server.local_addr());
+        let client = reqwest::Client::new();
+
+        for authorization in [
+            None,
+            Some("secret"),
+            Some("Bearer "),
+            Some("Bearer secre"),
+            Some("Basic secret"),
+        ] {
+            let mut request = client.get(&url);
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            let response = request.send().await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{authorization:?}"
+            );
+        }
+
+        // The body is checked only after the token.
+        let response = client
+            .patch(format!("{url}/127.0.0.1"))
+            .header(CONTENT_TYPE, "application/json")
+            .body("{")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        for (path, body, status) in [
+            ("/127.0.0.1", "{", StatusCode::BAD_REQUEST),
+            (
+                "/127.0.0.1",
+                r#"{ "capacity": -1 }"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                "/127.0.0.1",
+                r#"{ "leak_per_second": 256 }"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                "/127.0.0.1",
+                r#"{ "leak_per_second": 0 }"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            ("/127.0.0.1/exempt", "{}", StatusCode::UNPROCESSABLE_ENTITY),
+            (
+                "/127.0.0.1/exempt",
+                r#"{ "duration": "forever" }"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            // The end of the exemption would overflow.
+            (
+                "/127.0.0.1/exempt",
+                r#"{ "duration": 1.5e19 }"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let method = if path.ends_with("exempt") {
+                reqwest::Method::POST
+            } else {
+                reqwest::Method::PATCH
+            };
+            let response = client
+                .request(method, format!("{url}{path}"))
+                .bearer_auth("secret")
+                .header(CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{path} {body}");
+        }
+
+        // Nothing has been changed.
+        let body = client
+            .get(&url)
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"[]");
+
+        server.shutdown().await.unwrap();
+    }
+
+    #[test]
+    #[should_panic = "the admin token should not be empty"]
+    fn empty_token() {
+        let _ = Builder::new(Vec::new()).with_admin_token("");
+    }
+}
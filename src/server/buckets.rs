@@ -0,0 +1,365 @@
+#![warn(clippy::pedantic)]
+
+//! The buckets of the clients, created on their first request.
+//!
+//! The clients with an API key are identified by its name, see [`ApiKey`]. The other ones are
+//! identified by their IP address, when the server is started with [`Builder::spawn`]. Otherwise
+//! they share the bucket of [`UNKNOWN_CLIENT`].
+//!
+//! Each bucket follows the [`Tier`] of its client. The buckets restored from a snapshot keep its
+//! parameters until the next request of their client, since its tier is not known before.
+//!
+//! [`ApiKey`]: super::ApiKey
+//! [`Builder::spawn`]: super::Builder::spawn
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    ops::Not,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{extract::ConnectInfo, http::Extensions};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use tokio::time::Instant;
+
+use super::{error::Error, tiers::Tier};
+use crate::leaky_bucket::{BucketInfo, LeakyBucket, Snapshot};
+
+/// The identifier of the clients whose address is not known.
+pub(super) const UNKNOWN_CLIENT: &str = "unknown";
+
+/// The identifier of a client, which owns a bucket.
+#[derive(Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(super) struct ClientId(pub String);
+
+impl ClientId {
+    /// Returns the identifier of the client connected to the server, from the extensions of its
+    /// request.
+    pub fn from_connection(extensions: &Extensions) -> Self {
+        let id = extensions.get::<ConnectInfo<SocketAddr>>().map_or_else(
+            || UNKNOWN_CLIENT.to_owned(),
+            |ConnectInfo(addr)| addr.ip().to_string(),
+        );
+        Self(id)
+    }
+}
+
+/// The state of the bucket of a client, as reported by the admin API.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketStatus {
+    pub client: String,
+
+    #[serde(flatten)]
+    pub bucket: BucketInfo,
+
+    /// The remaining time in which the client is not charged, if any.
+    #[serde_as(as = "Option<DurationSecondsWithFrac<f64>>")]
+    pub exempt_for: Option<Duration>,
+}
+
+/// The bucket of a client.
+#[derive(Debug)]
+pub(super) struct ClientBucket {
+    pub bucket: LeakyBucket,
+
+    /// The time until which the client is not charged.
+    exempt_until: Option<Instant>,
+
+    /// The tier which sets the parameters of the bucket, unless changed by the admin API.
+    ///
+    /// It is not known for the buckets restored from a snapshot, until the next request.
+    tier: Option<Arc<Tier>>,
+}
+
+impl ClientBucket {
+    fn new(bucket: LeakyBucket, tier: Option<Arc<Tier>>) -> Self {
+        Self {
+            bucket,
+            exempt_until: None,
+            tier,
+        }
+    }
+
+    fn empty(tier: Arc<Tier>) -> Self {
+        Self::new(
+            LeakyBucket::empty(tier.capacity, tier.leak_per_second),
+            Some(tier),
+        )
+    }
+
+    /// Returns whether the client is currently not charged.
+    pub fn is_exempt(&self) -> bool {
+        self.exempt_for().is_some()
+    }
+
+    fn exempt_for(&self) -> Option<Duration> {
+        self.exempt_until
+            .map(|exempt_until| exempt_until.saturating_duration_since(Instant::now()))
+            .filter(|exempt_for| exempt_for.is_zero().not())
+    }
+}
+
+/// The buckets of all the clients.
+#[derive(Debug)]
+pub(super) struct Buckets {
+    /// The tier of the buckets created without a request by the admin API.
+    anonymous: Arc<Tier>,
+    clients: HashMap<ClientId, ClientBucket>,
+}
+
+impl Buckets {
+    /// Creates the buckets, restoring the given snapshots with their own parameters.
+    ///
+    /// The buckets take the parameters of the tier of their clients on their first request,
+    /// therefore the points above the anonymous capacity are not lost.
+    pub fn new(anonymous: Arc<Tier>, snapshots: BTreeMap<ClientId, Snapshot>) -> Self {
+        let clients = snapshots
+            .into_iter()
+            .map(|(client, snapshot)| {
+                let bucket = ClientBucket::new(LeakyBucket::restore(snapshot), None);
+                (client, bucket)
+            })
+            .collect();
+
+        Self { anonymous, clients }
+    }
+
+    /// Returns the snapshots of all the buckets.
+    pub fn snapshots(&self) -> BTreeMap<ClientId, Snapshot> {
+        self.clients
+            .iter()
+            .map(|(client, bucket)| (client.clone(), bucket.bucket.snapshot()))
+            .collect()
+    }
+
+    /// Returns the bucket of a client in the given tier, creating an empty one if needed.
+    ///
+    /// If the bucket was in another tier, it takes the parameters of the new one, keeping its
+    /// points.
+    pub fn get_or_insert(&mut self, client: ClientId, tier: &Arc<Tier>) -> &ClientBucket {
+        let bucket = self
+            .clients
+            .entry(client)
+            .or_insert_with(|| ClientBucket::empty(Arc::clone(tier)));
+
+        if bucket.tier.as_ref() != Some(tier) {
+            bucket.bucket = LeakyBucket::restore(Snapshot {
+                capacity: tier.capacity,
+                leak_per_second: tier.leak_per_second,
+                ..bucket.bucket.snapshot()
+            });
+            bucket.tier = Some(Arc::clone(tier));
+        }
+        bucket
+    }
+
+    /// Returns all the buckets.
+    pub fn iter(&self) -> impl Iterator<Item = (&ClientId, &ClientBucket)> {
+        self.clients.iter()
+    }
+
+    /// Returns the state of the bucket of a client, if any.
+    pub fn status(&self, client: &ClientId) -> Option<BucketStatus> {
+        self.clients.get(client).map(|bucket| BucketStatus {
+            client: client.0.clone(),
+            bucket: BucketInfo::new(&bucket.bucket),
+            exempt_for: bucket.exempt_for(),
+        })
+    }
+
+    /// Returns the state of all the buckets, sorted by client.
+    pub fn list(&self) -> Vec<BucketStatus> {
+        let mut clients: Vec<_> = self.clients.keys().collect();
+        clients.sort_unstable();
+        clients
+            .into_iter()
+            .filter_map(|client| self.status(client))
+            .collect()
+    }
+
+    /// Replaces the bucket of a client with an empty one, with the parameters of its tier and no
+    /// exemption.
+    ///
+    /// The anonymous tier is used if the tier of the client is not known.
+    pub fn reset(&mut self, client: &ClientId) -> BucketStatus {
+        let tier = self
+            .clients
+            .get(client)
+            .and_then(|bucket| bucket.tier.as_ref())
+            .unwrap_or(&self.anonymous);
+        let bucket = ClientBucket::empty(Arc::clone(tier));
+        self.clients.insert(client.clone(), bucket);
+        self.status(client)
+            .expect("the bucket has just been inserted")
+    }
+
+    /// Removes all the points from the bucket of a client.
+    pub fn drain(&mut self, client: &ClientId) -> BucketStatus {
+        self.update(client, |bucket| {
+            bucket.bucket = LeakyBucket::restore(Snapshot {
+                points: 0,
+                ..bucket.bucket.snapshot()
+            });
+        })
+    }
+
+    /// Changes the capacity and the leak of the bucket of a client, keeping its points.
+    ///
+    /// Points exceeding the new capacity are discarded. Returns an error if the leak is zero,
+    /// since the bucket would never empty.
+    pub fn configure(
+        &mut self,
+        client: &ClientId,
+        capacity: Option<u16>,
+        leak_per_second: Option<u8>,
+    ) -> Result<BucketStatus, Error> {
+        if leak_per_second == Some(0) {
+            return Err(Error::InvalidBucketChange(
+                "the leak per second must be positive",
+            ));
+        }
+
+        Ok(self.update(client, |bucket| {
+            let snapshot = bucket.bucket.snapshot();
+            bucket.bucket = LeakyBucket::restore(Snapshot {
+                capacity: capacity.unwrap_or(snapshot.capacity),
+                leak_per_second: leak_per_second.unwrap_or(snapshot.leak_per_second),
+                ..snapshot
+            });
+        }))
+    }
+
+    /// Stops charging a client for the given time, replacing any previous exemption.
+    ///
+    /// Returns an error if the end of the exemption cannot be represented.
+    pub fn exempt(&mut self, client: &ClientId, duration: Duration) -> Result<BucketStatus, Error> {
+        let exempt_until = Instant::now()
+            .checked_add(duration)
+            .ok_or(Error::InvalidBucketChange("the exemption is too long"))?;
+
+        Ok(self.update(client, |bucket| {
+            bucket.exempt_until = Some(exempt_until);
+        }))
+    }
+
+    /// Changes the bucket of a client, creating it if needed.
+    fn update(
+        &mut self,
+        client: &ClientId,
+        update: impl FnOnce(&mut ClientBucket),
+    ) -> BucketStatus {
+        let anonymous = &self.anonymous;
+        let bucket = self
+            .clients
+            .entry(client.clone())
+            .or_insert_with(|| ClientBucket::empty(Arc::clone(anonymous)));
+        update(bucket);
+        self.status(client).expect("the bucket exists")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+
+    #[test]
+    fn manage_buckets() {
+        let anonymous = Arc::new(Tier::new(500, 4));
+        let mut buckets = Buckets::new(Arc::clone(&anonymous), BTreeMap::new());
+        let alice = ClientId("alice".to_owned());
+        assert_eq!(buckets.status(&alice), None);
+
+        buckets
+            .get_or_insert(alice.clone(), &anonymous)
+            .bucket
+            .add(300)
+            .unwrap();
+        let status = buckets.configure(&alice, Some(200), None).unwrap();
+        assert_eq!(
+            status.bucket,
+            BucketInfo {
+                points: 200,
+                capacity: 200,
+                leak_per_second: 4,
+            }
+        );
+
+        let status = buckets.drain(&alice);
+        assert_eq!(status.bucket.points, 0);
+        assert_eq!(status.bucket.capacity, 200);
+
+        let status = buckets.exempt(&alice, Duration::from_mins(1)).unwrap();
+        assert!(status.exempt_for.unwrap() > Duration::from_secs(59));
+        assert!(buckets.get_or_insert(alice.clone(), &anonymous).is_exempt());
+        buckets.exempt(&alice, Duration::ZERO).unwrap();
+        assert!(buckets
+            .get_or_insert(alice.clone(), &anonymous)
+            .is_exempt()
+            .not());
+
+        let status = buckets.reset(&alice);
+        assert_eq!(status.bucket.capacity, 500);
+        let list = buckets.list();
+        assert_eq!(list, [status]);
+
+        // Invalid changes are refused without creating the bucket.
+        let bob = ClientId("bob".to_owned());
+        assert!(buckets.configure(&alice, Some(200), Some(0)).is_err());
+        assert!(buckets.exempt(&bob, Duration::MAX).is_err());
+        assert_eq!(buckets.list(), list);
+    }
+
+    #[test]
+    fn change_tier() {
+        let anonymous = Arc::new(Tier::new(500, 4));
+        let premium = Arc::new(Tier::new(5000, 50));
+        let mut snapshots = BTreeMap::new();
+        snapshots.insert(
+            ClientId("acme".to_owned()),
+            Snapshot {
+                points: 3000,
+                capacity: 5000,
+                leak_per_second: 50,
+                taken_at: SystemTime::now(),
+            },
+        );
+        snapshots.insert(
+            ClientId("127.0.0.1".to_owned()),
+            Snapshot {
+                points: 300,
+                capacity: 1000,
+                leak_per_second: 10,
+                taken_at: SystemTime::now(),
+            },
+        );
+        let mut buckets = Buckets::new(Arc::clone(&anonymous), snapshots);
+
+        // The points above the anonymous capacity are kept until the first request.
+        let acme = ClientId("acme".to_owned());
+        assert!(buckets.status(&acme).unwrap().bucket.points > 2900);
+        let bucket = &buckets.get_or_insert(acme.clone(), &premium).bucket;
+        assert_eq!(bucket.capacity(), 5000);
+        assert_eq!(bucket.leak_per_second(), 50);
+        assert!(bucket.points() > 2900);
+
+        // A snapshot taken with other parameters follows the current tier.
+        let bucket = &buckets
+            .get_or_insert(ClientId("127.0.0.1".to_owned()), &anonymous)
+            .bucket;
+        assert_eq!((bucket.capacity(), bucket.leak_per_second()), (500, 4));
+        assert!(bucket.points() > 250);
+
+        // The changes of the admin API are kept while the tier does not change.
+        buckets.configure(&acme, Some(1000), None).unwrap();
+        let bucket = &buckets.get_or_insert(acme.clone(), &premium).bucket;
+        assert_eq!(bucket.capacity(), 1000);
+        assert_eq!(buckets.reset(&acme).bucket.capacity, 5000);
+    }
+}
@@ -0,0 +1,443 @@
+#![warn(clippy::pedantic)]
+
+//! Fault injection, in order to test the clients against a misbehaving server.
+//!
+//! Each fault has its own probability and random generator, therefore enabling a fault does not
+//! change when the others happen. A fault with a seed is reproducible, as long as the requests are
+//! received in the same order.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{boxed, Body, Bytes, Full, HttpBody},
+    http::{header::CONTENT_LENGTH, HeaderMap, Method, Request, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use tokio::time::sleep;
+use tracing::debug;
+
+use crate::{
+    database::ServerQuery, leaky_bucket::BucketInfo, BUCKET_CAPACITY_HEADER,
+    BUCKET_LEAK_PER_SECOND_HEADER, BUCKET_POINTS_HEADER, RATELIMIT_LIMIT_HEADER,
+    RATELIMIT_POLICY_HEADER, RATELIMIT_REMAINING_HEADER, RATELIMIT_RESET_HEADER,
+};
+
+/// The faults injected by the server, all disabled by default.
+///
+/// The configuration can be deserialized, durations being expressed in seconds.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Chaos {
+    /// Delays the requests.
+    pub latency: Option<Latency>,
+
+    /// Answers with a random `5xx` status, without handling the request.
+    pub server_errors: Option<Fault>,
+
+    /// Handles the request, then closes the connection instead of sending the body.
+    pub dropped_connections: Option<Fault>,
+
+    /// Cuts the body of successful responses at a random length.
+    pub truncated_bodies: Option<Fault>,
+
+    /// Removes the headers describing the bucket.
+    pub missing_bucket_headers: Option<Fault>,
+
+    /// Replaces the headers describing the bucket with random points and leak.
+    pub lying_bucket_headers: Option<Fault>,
+
+    /// Serves a page next to the requested one.
+    pub out_of_order_pages: Option<Fault>,
+}
+
+/// The probability of a fault, and the seed of its random generator.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fault {
+    pub probability: f64,
+
+    /// The seed of the random generator. If missing, the fault is not reproducible.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// The delay added to the requests.
+#[serde_as]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    #[serde(flatten)]
+    pub fault: Fault,
+
+    /// The minimum delay.
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub min: Duration,
+
+    /// The maximum delay.
+    #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+    pub max: Duration,
+}
+
+impl Fault {
+    /// Creates a fault happening with the given probability.
+    #[must_use]
+    pub const fn new(probability: f64) -> Self {
+        Self {
+            probability,
+            seed: None,
+        }
+    }
+
+    /// Sets the seed of the random generator, making the fault reproducible.
+    #[must_use]
+    pub const fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// The state of the faults while the server runs.
+#[derive(Debug)]
+pub(super) struct ChaosState {
+    latency: Option<FaultState>,
+    latency_range: (Duration, Duration),
+    server_errors: Option<FaultState>,
+    dropped_connections: Option<FaultState>,
+    truncated_bodies: Option<FaultState>,
+    missing_bucket_headers: Option<FaultState>,
+    lying_bucket_headers: Option<FaultState>,
+    out_of_order_pages: Option<FaultState>,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    probability: f64,
+    rng: Mutex<StdRng>,
+}
+
+impl ChaosState {
+    pub fn new(chaos: &Chaos) -> Self {
+        let state = |fault: Option<Fault>| fault.map(FaultState::new);
+
+        Self {
+            latency: state(chaos.latency.map(|latency| latency.fault)),
+            latency_range: chaos
+                .latency
+                .map_or((Duration::ZERO, Duration::ZERO), |latency| {
+                    (latency.min.min(latency.max), latency.max.max(latency.min))
+                }),
+            server_errors: state(chaos.server_errors),
+            dropped_connections: state(chaos.dropped_connections),
+            truncated_bodies: state(chaos.truncated_bodies),
+            missing_bucket_headers: state(chaos.missing_bucket_headers),
+            lying_bucket_headers: state(chaos.lying_bucket_headers),
+            out_of_order_pages: state(chaos.out_of_order_pages),
+        }
+    }
+}
+
+impl FaultState {
+    fn new(fault: Fault) -> Self {
+        let rng = fault
+            .seed
+            .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+
+        Self {
+            probability: fault.probability,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Checks whether the fault happens, returning a value generated with its random generator.
+    fn roll<T>(fault: Option<&Self>, value: impl FnOnce(&mut StdRng) -> T) -> Option<T> {
+        let fault = fault?;
+        let mut rng = fault.rng.lock().unwrap_or_else(PoisonError::into_inner);
+        (rng.gen::<f64>() < fault.probability).then(|| value(&mut rng))
+    }
+}
+
+/// Injects the faults in the handling of a request.
+pub(super) async fn inject(
+    state: Arc<ChaosState>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if let Some(uri) = FaultState::roll(state.out_of_order_pages.as_ref(), |rng| {
+        other_page(request.method(), request.uri(), rng)
+    })
+    .flatten()
+    {
+        debug!("serving {uri} instead of {}", request.uri());
+        *request.uri_mut() = uri;
+    }
+
+    let (min, max) = state.latency_range;
+    if let Some(delay) = FaultState::roll(state.latency.as_ref(), |rng| rng.gen_range(min..=max)) {
+        sleep(delay).await;
+    }
+
+    if let Some(status) = FaultState::roll(state.server_errors.as_ref(), |rng| {
+        const STATUSES: [StatusCode; 4] = [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::GATEWAY_TIMEOUT,
+        ];
+        STATUSES[rng.gen_range(0..STATUSES.len())]
+    }) {
+        return (status, "injected fault").into_response();
+    }
+
+    let mut response = next.run(request).await;
+
+    if FaultState::roll(state.missing_bucket_headers.as_ref(), |_| ()).is_some() {
+        remove_bucket_headers(response.headers_mut());
+    }
+
+    if let Ok(info) = BucketInfo::decode(response.headers()) {
+        if let Some(lie) = FaultState::roll(state.lying_bucket_headers.as_ref(), |rng| BucketInfo {
+            points: rng.gen_range(0..=info.capacity),
+            capacity: info.capacity,
+            leak_per_second: rng.gen_range(1..=info.leak_per_second.saturating_mul(4).max(1)),
+        }) {
+            lie.encode(response.headers_mut());
+        }
+    }
+
+    if FaultState::roll(state.dropped_connections.as_ref(), |_| ()).is_some() {
+        let (mut parts, _) = response.into_parts();
+        parts.headers.remove(CONTENT_LENGTH);
+        return Response::from_parts(parts, boxed(DroppedBody));
+    }
+
+    if response.status().is_success() {
+        if let Some(cut) = FaultState::roll(state.truncated_bodies.as_ref(), Rng::gen::<f64>) {
+            return truncate_body(response, cut).await;
+        }
+    }
+
+    response
+}
+
+/// Returns the URI of a page next to the one requested, if a page is requested.
+fn other_page(method: &Method, uri: &Uri, rng: &mut StdRng) -> Option<Uri> {
+    if method != Method::GET || uri.path() != "/" {
+        return None;
+    }
+
+    let mut query: ServerQuery = serde_qs::from_str(uri.query().unwrap_or_default()).ok()?;
+    let page = query.page.unwrap_or(0);
+    query.page = Some(if page == 0 || rng.gen_bool(0.5) {
+        page + 1
+    } else {
+        page - 1
+    });
+
+    let query = serde_qs::to_string(&query).ok()?;
+    format!("/?{query}").parse().ok()
+}
+
+fn remove_bucket_headers(headers: &mut HeaderMap) {
+    for name in [
+        BUCKET_POINTS_HEADER,
+        BUCKET_CAPACITY_HEADER,
+        BUCKET_LEAK_PER_SECOND_HEADER,
+        RATELIMIT_LIMIT_HEADER,
+        RATELIMIT_REMAINING_HEADER,
+        RATELIMIT_RESET_HEADER,
+        RATELIMIT_POLICY_HEADER,
+    ] {
+        headers.remove(name);
+    }
+}
+
+/// Keeps the given fraction of the body, with a matching `Content-Length`.
+async fn truncate_body(response: Response, fraction: f64) -> Response {
+    let (mut parts, mut body) = response.into_parts();
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => bytes.extend_from_slice(&chunk),
+            Err(error) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+            }
+        }
+    }
+
+    // The fraction is in `[0, 1)`, therefore at least one byte is removed from non-empty bodies.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let len = (bytes.len() as f64 * fraction) as usize;
+    bytes.truncate(len);
+
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(bytes)))
+}
+
+/// A body which always fails, causing the connection to be closed.
+struct DroppedBody;
+
+impl HttpBody for DroppedBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Poll::Ready(Some(Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "injected fault",
+        ))))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::{
+        database::{fixtures, EntryId, RawEntry},
+        server::{Builder, Load, ServerHandle},
+    };
+
+    fn spawn(chaos: Chaos) -> ServerHandle {
+        Builder::new(fixtures::dataset(3))
+            .with_bucket(1000, 10)
+            .with_load(Load::none())
+            .with_chaos(chaos)
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap()
+    }
+
+    async fn get(server: &ServerHandle, query: &str) -> reqwest::Result<reqwest::Response> {
+        reqwest::get(format!("http://{}/?{query}", server.local_addr())).await
+    }
+
+    fn always() -> Fault {
+        Fault::new(1.).with_seed(42)
+    }
+
+    #[tokio::test]
+    async fn faults() {
+        let server = spawn(Chaos {
+            server_errors: Some(always()),
+            ..Chaos::default()
+        });
+        let response = get(&server, "").await.unwrap();
+        assert!(response.status().is_server_error());
+        assert!(response.headers().get(BUCKET_POINTS_HEADER).is_none());
+        server.shutdown().await.unwrap();
+
+        let server = spawn(Chaos {
+            missing_bucket_headers: Some(always()),
+            ..Chaos::default()
+        });
+        let response = get(&server, "").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(BucketInfo::decode(response.headers()).is_err());
+        server.shutdown().await.unwrap();
+
+        let server = spawn(Chaos {
+            lying_bucket_headers: Some(always()),
+            ..Chaos::default()
+        });
+        let response = get(&server, "page_size=1&fields[0]=name").await.unwrap();
+        let info = BucketInfo::decode(response.headers()).unwrap();
+        assert_eq!(info.capacity, 1000);
+        assert_ne!(info.points, 1);
+        server.shutdown().await.unwrap();
+
+        let server = spawn(Chaos {
+            dropped_connections: Some(always()),
+            ..Chaos::default()
+        });
+        let body = match get(&server, "").await {
+            Ok(response) => response.bytes().await,
+            Err(error) => Err(error),
+        };
+        assert!(body.is_err());
+        server.shutdown().await.unwrap();
+
+        let server = spawn(Chaos {
+            truncated_bodies: Some(always()),
+            ..Chaos::default()
+        });
+        let body = get(&server, "").await.unwrap().bytes().await.unwrap();
+        assert!(serde_json::from_slice::<Vec<RawEntry>>(&body).is_err());
+        server.shutdown().await.unwrap();
+
+        let server = spawn(Chaos {
+            out_of_order_pages: Some(always()),
+            ..Chaos::default()
+        });
+        let body = get(&server, "page=0&page_size=1")
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let entries: Vec<RawEntry> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(entries[0].id, Some(EntryId(1)));
+        server.shutdown().await.unwrap();
+
+        let delay = Duration::from_millis(200);
+        let server = spawn(Chaos {
+            latency: Some(Latency {
+                fault: Fault::new(1.),
+                min: delay,
+                max: delay,
+            }),
+            ..Chaos::default()
+        });
+        let start = Instant::now();
+        assert!(get(&server, "").await.unwrap().status().is_success());
+        assert!(start.elapsed() >= delay);
+        server.shutdown().await.unwrap();
+    }
+
+    #[test]
+    fn deserialize() {
+        let chaos: Chaos = serde_json::from_str(
+            r#"{
+                "latency": { "probability": 0.1, "seed": 7, "min": 0.5, "max": 2 },
+                "server_errors": { "probability": 0.05 }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            chaos,
+            Chaos {
+                latency: Some(Latency {
+                    fault: Fault::new(0.1).with_seed(7),
+                    min: Duration::from_millis(500),
+                    max: Duration::from_secs(2),
+                }),
+                server_errors: Some(Fault::new(0.05)),
+                ..Chaos::default()
+            }
+        );
+    }
+}
@@ -1,16 +1,17 @@
 #![warn(clippy::pedantic)]
 
-use std::collections::HashSet;
+use std::{borrow::Cow, collections::BTreeSet};
 
+use crate::database::{EntryId, GeoPoint2d, GeoShape, RawEntry, ServerField, ServerQuery};
 use serde::Serialize;
 use serde_with::skip_serializing_none;
-use workshop_rustlab_2022::database::{Entry, GeoPoint2d, GeoShape, ServerField};
 
 #[skip_serializing_none]
 #[derive(Debug, Default, Serialize)]
 pub struct PartialEntry<'a> {
+    id: Option<EntryId>,
     geo_point_2d: Option<&'a GeoPoint2d>,
-    geo_shape: Option<&'a GeoShape>,
+    geo_shape: Option<Cow<'a, GeoShape>>,
     name: Option<&'a str>,
     etichetta: Option<&'a str>,
     notetesto: Option<&'a str>,
@@ -38,7 +39,7 @@ pub struct PartialEntry<'a> {
 }
 
 impl<'a> PartialEntry<'a> {
-    pub fn from_entry_with_fields(entry: &'a Entry, fields: &HashSet<ServerField>) -> Self {
+    pub fn from_entry_with_fields(entry: &'a RawEntry, fields: &BTreeSet<ServerField>) -> Self {
         let mut out = Self::default();
 
         macro_rules! field {
@@ -49,8 +50,9 @@ impl<'a> PartialEntry<'a> {
 
         for field in fields {
             match field {
+                ServerField::Id => out.id = entry.id,
                 ServerField::GeoPoint2d => field!(geo_point_2d),
-                ServerField::GeoShape => field!(geo_shape),
+                ServerField::GeoShape => out.geo_shape = Some(Cow::Borrowed(&entry.geo_shape)),
                 ServerField::Name => field!(name),
                 ServerField::Etichetta => field!(etichetta),
                 ServerField::Notetesto => field!(notetesto),
@@ -92,11 +94,35 @@ impl<'a> PartialEntry<'a> {
 
         out
     }
+
+    /// Creates a partial entry containing the fields requested by the query, simplifying the
+    /// geometry if needed.
+    pub fn from_query(entry: &'a RawEntry, query: &ServerQuery) -> Self {
+        let mut out = if query.fields.is_empty() {
+            Self::from(entry)
+        } else {
+            Self::from_entry_with_fields(entry, &query.fields)
+        };
+
+        if let Some(tolerance) = query.simplify {
+            out.simplify_geo_shape(tolerance.get().into());
+        }
+        out
+    }
+
+    /// Simplifies the geometry of the shape, if present, using the given tolerance in meters.
+    pub fn simplify_geo_shape(&mut self, tolerance: f64) {
+        if let Some(geo_shape) = &mut self.geo_shape {
+            let GeoShape::Feature(feature) = geo_shape.to_mut();
+            feature.geometry = feature.geometry.simplify(tolerance);
+        }
+    }
 }
 
-impl<'a> From<&'a Entry> for PartialEntry<'a> {
-    fn from(entry: &'a Entry) -> Self {
-        let Entry {
+impl<'a> From<&'a RawEntry> for PartialEntry<'a> {
+    fn from(entry: &'a RawEntry) -> Self {
+        let RawEntry {
+            id,
             geo_point_2d,
             geo_shape,
             name,
@@ -125,8 +151,9 @@ impl<'a> From<&'a Entry> for PartialEntry<'a> {
             attivita_commerciali_produttive_5,
         } = entry;
 
+        let id = *id;
         let geo_point_2d = Some(geo_point_2d);
-        let geo_shape = Some(geo_shape);
+        let geo_shape = Some(Cow::Borrowed(geo_shape));
         let name = Some(name.as_str());
         let etichetta = Some(etichetta.as_str());
         let notetesto = Some(notetesto.as_str());
@@ -153,6 +180,7 @@ impl<'a> From<&'a Entry> for PartialEntry<'a> {
         let attivita_commerciali_produttive_5 = Some(attivita_commerciali_produttive_5.as_str());
 
         Self {
+            id,
             geo_point_2d,
             geo_shape,
             name,
@@ -0,0 +1,179 @@
+#![warn(clippy::pedantic)]
+
+//! Common error handling.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use axum::{
+    http::{
+        header::{RETRY_AFTER, WWW_AUTHENTICATE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    database::{EntryId, ServerField},
+    leaky_bucket::BucketInfo,
+};
+
+/// An error type.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The [`LeakyBucket`] does not have enough free capacity for a specific request.
+    ///
+    /// [`LeakyBucket`]: `crate::leaky_bucket::LeakyBucket`
+    NotEnoughCapacity {
+        /// The points of the request.
+        request: u16,
+
+        /// The current points in the leaky bucket.
+        points: u16,
+
+        /// The capacity of the leaky bucket.
+        capacity: u16,
+
+        /// The _leak_ of the bucket for each second.
+        ///
+        /// Every second the bucket is going to empty by this value.
+        leak_per_second: u8,
+
+        /// The time after which the request can be performed, if it can ever be.
+        retry_after: Option<Duration>,
+    },
+
+    /// No entry exists with the given identifier.
+    EntryNotFound(EntryId),
+
+    /// The entry sent by the client is not valid.
+    InvalidEntry(String),
+
+    /// The changes could not be persisted.
+    Storage(String),
+
+    /// No bucket exists for the given client.
+    BucketNotFound(String),
+
+    /// The change requested through the admin API would leave the bucket in an invalid state.
+    InvalidBucketChange(&'static str),
+
+    /// The admin token is missing or wrong.
+    Unauthorized,
+
+    /// The API key is not known.
+    InvalidApiKey,
+
+    /// The tier of the client does not allow some of the requested fields, or all of them.
+    FieldsNotAllowed(BTreeSet<ServerField>),
+
+    /// The tier of the client does not allow changes to the entries.
+    WriteNotAllowed,
+
+    /// The tier of the client does not allow such a large page.
+    PageSizeTooLarge {
+        /// The requested page size.
+        page_size: u16,
+
+        /// The largest page size allowed.
+        max: u16,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotEnoughCapacity {
+                request,
+                points,
+                capacity,
+                leak_per_second,
+                ..
+            } => {
+                let available = capacity - points;
+                write!(
+                    f,
+                    "Not enough capacity. Requested {request} points, available \
+                     {available}/{capacity} points (leak: {leak_per_second}/s)"
+                )
+            }
+            Error::EntryNotFound(id) => write!(f, "Entry {id} not found"),
+            Error::InvalidEntry(reason) => write!(f, "Invalid entry: {reason}"),
+            Error::Storage(reason) => write!(f, "Unable to store the changes: {reason}"),
+            Error::BucketNotFound(client) => write!(f, "No bucket for client {client}"),
+            Error::InvalidBucketChange(reason) => write!(f, "Invalid bucket change: {reason}"),
+            Error::Unauthorized => f.write_str("Missing or invalid admin token"),
+            Error::InvalidApiKey => f.write_str("Invalid API key"),
+            Error::FieldsNotAllowed(allowed) => {
+                f.write_str("Fields not allowed, only the following ones can be requested:")?;
+                for field in allowed {
+                    write!(f, " {}", field.to_str())?;
+                }
+                Ok(())
+            }
+            Error::WriteNotAllowed => f.write_str("Changes to the entries not allowed"),
+            Error::PageSizeTooLarge { page_size, max } => {
+                write!(f, "Page size {page_size} not allowed, the maximum is {max}")
+            }
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    /// Convert `Error` into an axum [`Response`].
+    ///
+    /// [`Response`]: axum::response::Response
+    fn into_response(self) -> Response {
+        match self {
+            error @ Error::NotEnoughCapacity {
+                points,
+                capacity,
+                leak_per_second,
+                retry_after,
+                ..
+            } => {
+                let mut headers = HeaderMap::new();
+                BucketInfo {
+                    points,
+                    capacity,
+                    leak_per_second,
+                }
+                .encode(&mut headers);
+
+                // `Retry-After` only supports whole seconds.
+                if let Some(retry_after) = retry_after {
+                    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                    headers.insert(RETRY_AFTER, HeaderValue::from(seconds));
+                }
+
+                (StatusCode::TOO_MANY_REQUESTS, headers, error.to_string()).into_response()
+            }
+            error @ (Error::EntryNotFound(_) | Error::BucketNotFound(_)) => {
+                (StatusCode::NOT_FOUND, error.to_string()).into_response()
+            }
+            error @ (Error::InvalidEntry(_) | Error::InvalidBucketChange(_)) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()).into_response()
+            }
+            error @ Error::Storage(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+            }
+            error @ Error::InvalidApiKey => {
+                (StatusCode::UNAUTHORIZED, error.to_string()).into_response()
+            }
+            error @ (Error::FieldsNotAllowed(_)
+            | Error::WriteNotAllowed
+            | Error::PageSizeTooLarge { .. }) => {
+                (StatusCode::FORBIDDEN, error.to_string()).into_response()
+            }
+            error @ Error::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, "Bearer")],
+                error.to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+#![warn(clippy::pedantic)]
+
+//! Entity tags, used to answer conditional requests.
+//!
+//! See [RFC 9110](https://www.rfc-editor.org/rfc/rfc9110#name-etag).
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+/// Computes the strong entity tag of a response body, quotes included.
+///
+/// The body already depends on both the entries and the requested fields, therefore the tag
+/// changes whenever one of them does. The hash is not guaranteed to be stable across different
+/// builds of the server: this only causes a cache miss for clients.
+pub fn from_body(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Checks whether the value of an `If-None-Match` header matches the given entity tag.
+///
+/// The header can contain a list of tags or `*`. As required for `If-None-Match`, the weak
+/// comparison is used, therefore the `W/` prefix is ignored.
+pub fn matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
@@ -0,0 +1,352 @@
+#![warn(clippy::pedantic)]
+
+//! The simulated load of other clients, which consumes the budget of the bucket.
+//!
+//! The load is made of _sporadic_ points, randomly added whenever a request is handled, and of
+//! scripted [`Profile`]s, which add points over time independently of the requests. The load can
+//! be deserialized, for instance from a JSON file, durations being expressed in seconds.
+
+use std::{ops::Not, time::Duration};
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DurationSecondsWithFrac};
+use tokio::time::Instant;
+
+use crate::LeakyBucket;
+
+/// The load of other clients.
+///
+/// The default load only adds sporadic points, see [`Sporadic::default`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Load {
+    /// The points randomly added when a request is handled, if any.
+    pub sporadic: Option<Sporadic>,
+
+    /// The scripted load over time, relative to the start of the server.
+    pub profiles: Vec<Profile>,
+}
+
+/// Points randomly added to the bucket when a request is handled.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sporadic {
+    /// The probability of adding points for each request.
+    pub probability: f64,
+
+    /// The distribution of the points added.
+    pub amount: Amount,
+}
+
+/// A distribution of points.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Amount {
+    /// Always the same points.
+    Fixed(u16),
+
+    /// Uniformly distributed points, bounds included.
+    Uniform { min: u16, max: u16 },
+
+    /// Exponentially distributed points, with the given mean and capped at `max`.
+    ///
+    /// Most of the times few points are added, but large amounts are possible.
+    Exponential { mean: f64, max: u16 },
+}
+
+/// A scripted load over time.
+///
+/// The times are relative to the start of the server.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Profile {
+    /// Adds `points` at once.
+    Burst {
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        at: Duration,
+        points: u16,
+    },
+
+    /// Adds points for `duration`, at a rate linearly changing from `from` to `to` points per
+    /// second.
+    Ramp {
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        start: Duration,
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        duration: Duration,
+        from: u16,
+        to: u16,
+    },
+
+    /// Adds `points` every `period`, forever.
+    Periodic {
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        start: Duration,
+        #[serde_as(as = "DurationSecondsWithFrac<f64>")]
+        period: Duration,
+        points: u16,
+    },
+}
+
+impl Load {
+    /// Creates a load which never adds any point.
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            sporadic: None,
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl Default for Load {
+    fn default() -> Self {
+        Self {
+            sporadic: Some(Sporadic::default()),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl Default for Sporadic {
+    /// From 1 to 4 points, for 15% of the requests.
+    fn default() -> Self {
+        Self {
+            probability: 0.15,
+            amount: Amount::Uniform { min: 1, max: 4 },
+        }
+    }
+}
+
+impl Amount {
+    /// Returns a random amount of points.
+    fn sample(&self, rng: &mut StdRng) -> u16 {
+        match *self {
+            Amount::Fixed(points) => points,
+            Amount::Uniform { min, max } => rng.gen_range(min.min(max)..=max.max(min)),
+            Amount::Exponential { mean, max } => {
+                // Inverse transform sampling, `1 - x` is never zero.
+                let x: f64 = rng.gen();
+                whole_points(-mean.max(0.) * (1. - x).ln()).min(max)
+            }
+        }
+    }
+}
+
+impl Profile {
+    /// Returns the points added in the given interval, `from` included and `to` excluded.
+    ///
+    /// The points of a ramp are not integers, the remainder is carried over by [`LoadState`].
+    fn points_between(&self, from: Duration, to: Duration) -> f64 {
+        match *self {
+            Profile::Burst { at, points } => {
+                if from <= at && at < to {
+                    points.into()
+                } else {
+                    0.
+                }
+            }
+            Profile::Ramp {
+                start,
+                duration,
+                from: from_rate,
+                to: to_rate,
+            } => {
+                let end = start + duration;
+                let (a, b) = (from.max(start), to.min(end));
+                if a >= b {
+                    return 0.;
+                }
+
+                let rate = |time: Duration| {
+                    let progress =
+                        time.saturating_sub(start).as_secs_f64() / duration.as_secs_f64();
+                    f64::from(from_rate) + (f64::from(to_rate) - f64::from(from_rate)) * progress
+                };
+                b.saturating_sub(a).as_secs_f64() * (rate(a) + rate(b)) / 2.
+            }
+            Profile::Periodic {
+                start,
+                period,
+                points,
+            } => {
+                if period.is_zero() {
+                    return 0.;
+                }
+
+                // The number of events before the given time.
+                let events = |time: Duration| {
+                    time.checked_sub(start).map_or(0., |elapsed| {
+                        (elapsed.as_secs_f64() / period.as_secs_f64()).ceil()
+                    })
+                };
+                (events(to) - events(from)) * f64::from(points)
+            }
+        }
+    }
+}
+
+/// The state of the load while the server runs.
+#[derive(Debug)]
+pub(super) struct LoadState {
+    load: Load,
+    rng: StdRng,
+    started: Instant,
+
+    /// The time, relative to `started`, up to which the profiles have been applied.
+    applied_until: Duration,
+
+    /// The fraction of point not added yet.
+    carry: f64,
+}
+
+impl LoadState {
+    pub fn new(load: Load, rng: StdRng) -> Self {
+        Self {
+            load,
+            rng,
+            started: Instant::now(),
+            applied_until: Duration::ZERO,
+            carry: 0.,
+        }
+    }
+
+    /// Returns whether there are any profiles, which must be applied periodically.
+    pub fn has_profiles(&self) -> bool {
+        self.load.profiles.is_empty().not()
+    }
+
+    /// Randomly adds points to the bucket, as done for each request.
+    ///
+    /// Returns the points added, which can exceed the ones actually fitting in the bucket.
+    pub fn apply_sporadic(&mut self, bucket: &LeakyBucket) -> u16 {
+        let Some(sporadic) = &self.load.sporadic else {
+            return 0;
+        };
+
+        if self.rng.gen::<f64>() < sporadic.probability {
+            let points = sporadic.amount.sample(&mut self.rng);
+            bucket.saturating_add(points);
+            points
+        } else {
+            0
+        }
+    }
+
+    /// Returns the whole points of the profiles since the last time they were applied.
+    ///
+    /// The points are the same for all the buckets.
+    pub fn apply_profiles(&mut self) -> u16 {
+        let now = self.started.elapsed();
+        let points = self
+            .load
+            .profiles
+            .iter()
+            .map(|profile| profile.points_between(self.applied_until, now))
+            .sum::<f64>()
+            + self.carry;
+        self.applied_until = now;
+
+        let whole = points.floor();
+        self.carry = points - whole;
+        whole_points(whole)
+    }
+}
+
+/// Converts a non-negative amount of points, saturating to the maximum.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn whole_points(points: f64) -> u16 {
+    // The conversion saturates, and NaN becomes zero.
+    points.round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn profiles() {
+        let secs = Duration::from_secs;
+
+        let burst = Profile::Burst {
+            at: secs(2),
+            points: 50,
+        };
+        assert_eq!(burst.points_between(secs(0), secs(2)), 0.);
+        assert_eq!(burst.points_between(secs(2), secs(3)), 50.);
+
+        let ramp = Profile::Ramp {
+            start: secs(10),
+            duration: secs(10),
+            from: 0,
+            to: 10,
+        };
+        assert_eq!(ramp.points_between(secs(0), secs(10)), 0.);
+        assert_eq!(ramp.points_between(secs(10), secs(20)), 50.);
+        assert_eq!(ramp.points_between(secs(15), secs(30)), 37.5);
+
+        let periodic = Profile::Periodic {
+            start: secs(1),
+            period: secs(2),
+            points: 5,
+        };
+        assert_eq!(periodic.points_between(secs(0), secs(1)), 0.);
+        assert_eq!(periodic.points_between(secs(0), secs(2)), 5.);
+        assert_eq!(periodic.points_between(secs(2), secs(7)), 10.);
+        assert_eq!(periodic.points_between(secs(0), secs(8)), 20.);
+    }
+
+    #[test]
+    fn seeded_sporadic_load() {
+        let points = |seed| {
+            let bucket = LeakyBucket::empty(u16::MAX, 1);
+            let sporadic = Sporadic {
+                probability: 0.5,
+                amount: Amount::Exponential {
+                    mean: 10.,
+                    max: 100,
+                },
+            };
+            let load = Load {
+                sporadic: Some(sporadic),
+                profiles: Vec::new(),
+            };
+            let mut state = LoadState::new(load, StdRng::seed_from_u64(seed));
+            for _ in 0..100 {
+                state.apply_sporadic(&bucket);
+            }
+            bucket.points()
+        };
+
+        assert_eq!(points(1), points(1));
+        assert_ne!(points(1), points(2));
+    }
+
+    #[test]
+    fn deserialize() {
+        let load: Load = serde_json::from_str(
+            r#"{
+                "sporadic": { "probability": 0.3, "amount": { "fixed": 2 } },
+                "profiles": [
+                    { "kind": "burst", "at": 1.5, "points": 100 },
+                    { "kind": "periodic", "start": 0, "period": 10, "points": 20 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            load.profiles[0],
+            Profile::Burst {
+                at: Duration::from_millis(1500),
+                points: 100,
+            }
+        );
+
+        let load: Load = serde_json::from_str(r#"{ "sporadic": null }"#).unwrap();
+        assert_eq!(load, Load::none());
+    }
+}
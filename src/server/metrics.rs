@@ -0,0 +1,281 @@
+#![warn(clippy::pedantic)]
+
+//! The metrics of the server, exposed in the Prometheus text format.
+//!
+//! The responses and their latency are measured by a middleware, the costs and the state of the
+//! bucket by the handler of the requests.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use tokio::time::Instant;
+
+use super::buckets::Buckets;
+
+/// The upper bounds of the buckets of the latency histogram, in seconds.
+const LATENCY_BOUNDS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.,
+];
+
+/// The upper bounds of the buckets of the query cost histogram, in points.
+const QUERY_COST_BOUNDS: &[f64] = &[1., 5., 10., 25., 50., 100., 250., 500., 1000., 2500., 5000.];
+
+/// The metrics collected while the server runs.
+#[derive(Debug)]
+pub(super) struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// The number of responses by status.
+    responses: BTreeMap<u16, u64>,
+    latency: Histogram,
+    query_cost: Histogram,
+
+    /// The points and the capacity of the bucket of each client.
+    buckets: BTreeMap<String, (u16, u16)>,
+    sporadic_points: u64,
+}
+
+/// A histogram with fixed buckets.
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+
+    /// The number of observations for each bucket, not cumulative. The last one is for the
+    /// observations above all the bounds.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                responses: BTreeMap::new(),
+                latency: Histogram::new(LATENCY_BOUNDS),
+                query_cost: Histogram::new(QUERY_COST_BOUNDS),
+                buckets: BTreeMap::new(),
+                sporadic_points: 0,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records a response sent to a client.
+    pub fn record_response(&self, status: StatusCode, latency: Duration) {
+        let mut inner = self.lock();
+        *inner.responses.entry(status.as_u16()).or_default() += 1;
+        inner.latency.observe(latency.as_secs_f64());
+    }
+
+    /// Records the cost of an operation, whether the bucket had enough capacity or not.
+    pub fn record_cost(&self, cost: u16) {
+        self.lock().query_cost.observe(cost.into());
+    }
+
+    /// Records the current state of the buckets.
+    pub fn record_buckets(&self, buckets: &Buckets) {
+        let mut inner = self.lock();
+        inner.buckets = buckets
+            .iter()
+            .map(|(client, bucket)| {
+                let bucket = &bucket.bucket;
+                (client.0.clone(), (bucket.points(), bucket.capacity()))
+            })
+            .collect();
+    }
+
+    /// Records the points added by the sporadic load.
+    pub fn record_sporadic_points(&self, points: u16) {
+        self.lock().sporadic_points += u64::from(points);
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    ///
+    /// The depth of the queue of the handler is measured by the caller.
+    pub fn render(&self, queue_depth: usize) -> String {
+        let inner = self.lock();
+        let mut out = String::new();
+        inner
+            .write(&mut out, queue_depth)
+            .expect("writing to a string never fails");
+        out
+    }
+}
+
+impl Inner {
+    fn write(&self, out: &mut String, queue_depth: usize) -> fmt::Result {
+        header(
+            out,
+            "server_responses_total",
+            "counter",
+            "The responses sent, by status.",
+        )?;
+        for (status, count) in &self.responses {
+            writeln!(out, "server_responses_total{{status=\"{status}\"}} {count}")?;
+        }
+
+        header(
+            out,
+            "server_response_duration_seconds",
+            "histogram",
+            "The time needed to send the responses.",
+        )?;
+        self.latency
+            .write(out, "server_response_duration_seconds")?;
+
+        header(
+            out,
+            "server_query_cost_points",
+            "histogram",
+            "The cost of the operations, including the ones rejected for lack of capacity.",
+        )?;
+        self.query_cost.write(out, "server_query_cost_points")?;
+
+        header(
+            out,
+            "server_bucket_points",
+            "gauge",
+            "The points in the bucket of each client.",
+        )?;
+        for (client, (points, _)) in &self.buckets {
+            writeln!(out, "server_bucket_points{{client=\"{client}\"}} {points}")?;
+        }
+
+        header(
+            out,
+            "server_bucket_capacity_points",
+            "gauge",
+            "The capacity of the bucket of each client.",
+        )?;
+        for (client, (_, capacity)) in &self.buckets {
+            writeln!(
+                out,
+                "server_bucket_capacity_points{{client=\"{client}\"}} {capacity}"
+            )?;
+        }
+
+        header(
+            out,
+            "server_sporadic_points_total",
+            "counter",
+            "The points added to the bucket by the sporadic load.",
+        )?;
+        writeln!(out, "server_sporadic_points_total {}", self.sporadic_points)?;
+
+        header(
+            out,
+            "server_queue_depth",
+            "gauge",
+            "The messages waiting to be handled.",
+        )?;
+        writeln!(out, "server_queue_depth {queue_depth}")
+    }
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let index = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[index] += 1;
+        self.sum += value;
+    }
+
+    fn write(&self, out: &mut String, name: &str) -> fmt::Result {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}")?;
+        }
+
+        let count: u64 = self.counts.iter().sum();
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}")?;
+        writeln!(out, "{name}_sum {}", self.sum)?;
+        writeln!(out, "{name}_count {count}")
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} {kind}")
+}
+
+/// Records the status and the latency of the responses.
+pub(super) async fn measure(
+    metrics: Arc<Metrics>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let start = Instant::now();
+    let response = next.run(request).await;
+    metrics.record_response(response.status(), start.elapsed());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{buckets::ClientId, tiers::Tier};
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.record_response(StatusCode::OK, Duration::from_millis(3));
+        metrics.record_response(StatusCode::OK, Duration::from_millis(30));
+        metrics.record_response(StatusCode::TOO_MANY_REQUESTS, Duration::from_secs(20));
+        metrics.record_cost(270);
+        metrics.record_sporadic_points(3);
+        let tier = Arc::new(Tier::new(500, 4));
+        let mut buckets = Buckets::new(Arc::clone(&tier), BTreeMap::new());
+        buckets
+            .get_or_insert(ClientId("127.0.0.1".to_owned()), &tier)
+            .bucket
+            .add(270)
+            .unwrap();
+        metrics.record_buckets(&buckets);
+
+        let text = metrics.render(2);
+        let lines: Vec<_> = text.lines().collect();
+        for line in [
+            "# TYPE server_responses_total counter",
+            "server_responses_total{status=\"200\"} 2",
+            "server_responses_total{status=\"429\"} 1",
+            "server_response_duration_seconds_bucket{le=\"0.001\"} 0",
+            "server_response_duration_seconds_bucket{le=\"0.005\"} 1",
+            "server_response_duration_seconds_bucket{le=\"10\"} 2",
+            "server_response_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "server_response_duration_seconds_count 3",
+            "server_query_cost_points_bucket{le=\"250\"} 0",
+            "server_query_cost_points_bucket{le=\"500\"} 1",
+            "server_query_cost_points_sum 270",
+            "server_bucket_points{client=\"127.0.0.1\"} 270",
+            "server_bucket_capacity_points{client=\"127.0.0.1\"} 500",
+            "server_sporadic_points_total 3",
+            "server_queue_depth 2",
+        ] {
+            assert!(lines.contains(&line), "missing {line:?} in:\n{text}");
+        }
+    }
+}
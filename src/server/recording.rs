@@ -0,0 +1,171 @@
+#![warn(clippy::pedantic)]
+
+//! The recording of the requests handled by the server, in order to replay them later.
+//!
+//! Each request is appended as a JSON [`Record`] on its own line. The `replay` binary re-issues the
+//! recorded requests against another server, reporting the differences in the responses.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::ConnectInfo,
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::leaky_bucket::BucketInfo;
+
+/// A request handled by the server, with the outcome.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    /// When the request was received.
+    pub timestamp: SystemTime,
+
+    /// The method of the request.
+    pub method: String,
+
+    /// The path and the query of the request.
+    pub uri: String,
+
+    /// The IP address of the client, if known.
+    ///
+    /// It is only known when the server is started with [`Builder::spawn`].
+    ///
+    /// [`Builder::spawn`]: super::Builder::spawn
+    pub client: Option<String>,
+
+    /// The state of the bucket sent in the response headers, if any.
+    pub bucket: Option<BucketInfo>,
+
+    /// The status of the response.
+    pub status: u16,
+
+    /// The size of the response body, if known in advance.
+    pub size: Option<u64>,
+}
+
+/// Appends the records to a file.
+#[derive(Debug)]
+pub(super) struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Opens the file at the given path, appending to the existing records.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn append(&self, record: &Record) {
+        let mut line = serde_json::to_vec(record).expect("records should always be serializable");
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(error) = file.write_all(&line) {
+            warn!("unable to record the request: {error}");
+        }
+    }
+}
+
+/// Records the request and its response.
+pub(super) async fn record(
+    recorder: Arc<Recorder>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let timestamp = SystemTime::now();
+    let method = request.method().to_string();
+    let uri = request
+        .uri()
+        .path_and_query()
+        .map_or_else(|| request.uri().path().to_owned(), ToString::to_string);
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+    let response = next.run(request).await;
+
+    recorder.append(&Record {
+        timestamp,
+        method,
+        uri,
+        client,
+        bucket: BucketInfo::decode(response.headers()).ok(),
+        status: response.status().as_u16(),
+        size: response.body().size_hint().exact(),
+    });
+
+    response
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::{
+        database::fixtures,
+        server::{Builder, Load},
+    };
+
+    #[tokio::test]
+    async fn record_requests() {
+        let path = env::temp_dir().join(format!("recording-test-{}.jsonl", process::id()));
+
+        let server = Builder::new(fixtures::dataset(3))
+            .with_bucket(1000, 10)
+            .with_load(Load::none())
+            .with_recording(&path)
+            .spawn(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .unwrap();
+        let url = format!("http://{}", server.local_addr());
+        reqwest::get(format!("{url}/?page_size=1&fields[0]=name"))
+            .await
+            .unwrap();
+        reqwest::get(format!("{url}/entries/100")).await.unwrap();
+        server.shutdown().await.unwrap();
+
+        let records: Vec<Record> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].method, "GET");
+        assert_eq!(records[0].uri, "/?page_size=1&fields[0]=name");
+        assert_eq!(records[0].client.as_deref(), Some("127.0.0.1"));
+        assert_eq!(
+            records[0].bucket,
+            Some(BucketInfo {
+                points: 1,
+                capacity: 1000,
+                leak_per_second: 10,
+            })
+        );
+        assert_eq!(records[0].status, 200);
+        assert!(records[0].size.is_some());
+        assert_eq!(records[1].status, 404);
+        assert!(records[0].timestamp <= records[1].timestamp);
+    }
+}
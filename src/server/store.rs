@@ -0,0 +1,843 @@
+#![warn(clippy::pedantic)]
+
+//! The mutable storage of the database entries.
+//!
+//! The entries are kept in memory, and every change is appended to a _write-ahead log_: a write
+//! operation is only confirmed, and visible to the readers, once its record reaches the disk. At startup the log is replayed
+//! over the base database, therefore edits are not lost across restarts. The log starts with a
+//! checksum of the base database, and it is refused if the database changed: its records would
+//! apply to the wrong entries.
+//!
+//! The records are written by a dedicated thread, so that waiting for the disk does not block the
+//! other requests. The log grows with every change while the server runs, and it is compacted at
+//! startup to the differences from the base database.
+//!
+//! # Updating the base database
+//!
+//! The entries without a stored `id` are numbered following their order, therefore a new dump
+//! would change their identifiers, and the server refuses to start with the old log. In order to
+//! update the base database:
+//!
+//! 1. export the entries from the running server: they include the identifiers and the changes in
+//!    the log;
+//! 2. merge the new dump into them, matching the entries by `name` and `numeromoderno` and keeping
+//!    their `id`, while the new entries can omit it;
+//! 3. stop the server, replace the base database with the result and remove the log.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    hash::Hasher,
+    io::{self, BufRead, BufReader, Write},
+    iter,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    database::{Entry, EntryId, GeoShape, RawEntry},
+    fnv::Fnv1a,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+use super::error::Error;
+
+/// The entries of the database, backed by a write-ahead log.
+#[derive(Debug)]
+pub struct Store {
+    /// The entries visible to the readers, whose changes have been persisted.
+    entries: BTreeMap<EntryId, RawEntry>,
+
+    /// The changes waiting for their records to be written, in order.
+    pending: VecDeque<PendingChange>,
+    next_id: u64,
+
+    /// The number of records sent to the write-ahead log.
+    queued: u64,
+
+    /// The writer of the write-ahead log, missing if the changes are kept in memory only.
+    wal: Option<WalWriter>,
+}
+
+/// A change of the [`Store`] waiting for its record to be written.
+#[derive(Debug)]
+struct PendingChange {
+    /// The position of the record in the queue of the write-ahead log.
+    record: u64,
+    id: EntryId,
+
+    /// The new entry, missing if it is deleted.
+    entry: Option<RawEntry>,
+}
+
+/// A write operation on the [`Store`].
+#[derive(Debug)]
+pub enum WriteOperation {
+    Create(RawEntry),
+    Replace(EntryId, RawEntry),
+
+    /// Replaces only the given fields of the entry.
+    Patch(EntryId, Map<String, Value>),
+    Delete(EntryId),
+}
+
+/// The result of a successful [`WriteOperation`].
+#[derive(Debug)]
+pub enum WriteOutcome {
+    Created(EntryId),
+    Updated,
+    Deleted,
+}
+
+/// Waits for a write operation to reach the write-ahead log.
+#[derive(Debug)]
+#[must_use = "the operation is not persisted yet"]
+pub struct Persisted(Option<oneshot::Receiver<Result<(), String>>>);
+
+impl Persisted {
+    /// Waits for the record of the operation to be written, if the store has a write-ahead log.
+    pub async fn wait(self) -> Result<(), Error> {
+        let Some(receiver) = self.0 else {
+            return Ok(());
+        };
+
+        match receiver.await {
+            Ok(result) => result.map_err(Error::Storage),
+            Err(_) => Err(Error::Storage(
+                "the write-ahead log writer stopped".to_owned(),
+            )),
+        }
+    }
+}
+
+/// A record of the write-ahead log.
+///
+/// Each record is stored as a single line of JSON. The entry type is generic in order to avoid
+/// cloning entries while writing the log.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum WalRecord<E = RawEntry> {
+    /// The first record, identifying the base database.
+    Header {
+        base_checksum: String,
+    },
+    Put {
+        id: EntryId,
+        entry: E,
+    },
+    Delete {
+        id: EntryId,
+    },
+}
+
+impl Store {
+    /// Creates the store from the base database, keeping the changes in memory only.
+    ///
+    /// Returns an error if an identifier is used by more than one entry.
+    pub fn in_memory(base: Vec<RawEntry>) -> io::Result<Self> {
+        let entries = base_entries(base)?;
+        Ok(Self {
+            next_id: next_id(&entries),
+            entries,
+            pending: VecDeque::new(),
+            queued: 0,
+            wal: None,
+        })
+    }
+
+    /// Creates the store from the base database, replaying the write-ahead log at `wal_path`.
+    ///
+    /// The log is created if missing. A truncated last record, which can be left by a crash while
+    /// writing, is discarded. If the log contains more than the differences from the base
+    /// database, it is compacted.
+    ///
+    /// Returns an error if the log was written over a different base database, or if an
+    /// identifier is used by more than one entry.
+    pub fn open(base: Vec<RawEntry>, wal_path: &Path) -> io::Result<Self> {
+        if let Some(parent) = wal_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let base_checksum = base_checksum(&base)?;
+        let base = base_entries(base)?;
+        let mut entries = base.clone();
+        let mut next_id = next_id(&entries);
+
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(wal_path)?;
+        let mut wal_len = replay(&wal, &base_checksum, &mut entries, &mut next_id)?;
+
+        let compacted = compact(base_checksum, &base, &entries, next_id)?;
+        let compacted_len = u64::try_from(compacted.len()).unwrap();
+        // An empty log, or one with a truncated header, only needs the header.
+        if compacted_len < wal_len || wal_len == 0 {
+            wal = replace_wal(wal_path, &compacted)?;
+            wal_len = compacted_len;
+        } else {
+            wal.set_len(wal_len)?;
+        }
+
+        Ok(Self {
+            entries,
+            pending: VecDeque::new(),
+            next_id,
+            queued: 0,
+            wal: Some(WalWriter::spawn(wal, wal_len)?),
+        })
+    }
+
+    /// Returns the entries of the given page, sorted by identifier.
+    pub fn page(&self, page: usize, page_size: u16) -> impl Iterator<Item = &RawEntry> {
+        let page_size = usize::from(page_size);
+        self.entries
+            .values()
+            .skip(page.saturating_mul(page_size))
+            .take(page_size)
+    }
+
+    /// Validates a write operation and appends it to the write-ahead log.
+    ///
+    /// The following operations are validated against the change, while it is visible to the
+    /// readers only after [`Store::commit_persisted`] once the operation is [`Persisted`]. If the
+    /// record cannot be written, the change is never applied.
+    pub fn apply(&mut self, operation: WriteOperation) -> Result<(WriteOutcome, Persisted), Error> {
+        match operation {
+            WriteOperation::Create(entry) => {
+                if entry.id.is_some() {
+                    return Err(Error::InvalidEntry(
+                        "the identifier is assigned by the server".to_owned(),
+                    ));
+                }
+
+                validate(&entry)?;
+                let id = EntryId(self.next_id);
+                let persisted = self.put(id, entry)?;
+                self.next_id += 1;
+                Ok((WriteOutcome::Created(id), persisted))
+            }
+            WriteOperation::Replace(id, entry) => {
+                self.latest(id)?;
+                check_id(id, &entry)?;
+                validate(&entry)?;
+                let persisted = self.put(id, entry)?;
+                Ok((WriteOutcome::Updated, persisted))
+            }
+            WriteOperation::Patch(id, fields) => {
+                let Value::Object(mut patched) = serde_json::to_value(self.latest(id)?)
+                    .map_err(|error| Error::Storage(error.to_string()))?
+                else {
+                    unreachable!("entries are always serialized as objects");
+                };
+                patched.extend(fields);
+
+                let entry = serde_json::from_value(Value::Object(patched))
+                    .map_err(|error| Error::InvalidEntry(error.to_string()))?;
+                check_id(id, &entry)?;
+                validate(&entry)?;
+                let persisted = self.put(id, entry)?;
+                Ok((WriteOutcome::Updated, persisted))
+            }
+            WriteOperation::Delete(id) => {
+                self.latest(id)?;
+                let persisted = self.append(&WalRecord::<&RawEntry>::Delete { id })?;
+                self.stage(id, None);
+                Ok((WriteOutcome::Deleted, persisted))
+            }
+        }
+    }
+
+    /// Returns the entry with the given identifier.
+    pub fn get(&self, id: EntryId) -> Result<&RawEntry, Error> {
+        self.entries.get(&id).ok_or(Error::EntryNotFound(id))
+    }
+
+    /// Applies the changes whose records have been written, discarding the remaining ones if the
+    /// write-ahead log failed.
+    pub fn commit_persisted(&mut self) {
+        let Some(wal) = &self.wal else {
+            return;
+        };
+
+        // The records written before a failure are counted before it is flagged.
+        let failed = wal.failed.load(Ordering::Acquire);
+        let written = wal.written.load(Ordering::Acquire);
+        while let Some(change) = self.pending.pop_front() {
+            if change.record >= written {
+                self.pending.push_front(change);
+                break;
+            }
+            apply_change(&mut self.entries, change.id, change.entry);
+        }
+
+        if failed {
+            self.pending.clear();
+        }
+    }
+
+    /// Returns the entry with the given identifier, including the changes not persisted yet.
+    fn latest(&self, id: EntryId) -> Result<&RawEntry, Error> {
+        let pending = self.pending.iter().rev().find(|change| change.id == id);
+        match pending {
+            Some(change) => change.entry.as_ref(),
+            None => self.entries.get(&id),
+        }
+        .ok_or(Error::EntryNotFound(id))
+    }
+
+    fn put(&mut self, id: EntryId, mut entry: RawEntry) -> Result<Persisted, Error> {
+        entry.id = Some(id);
+        let persisted = self.append(&WalRecord::Put { id, entry: &entry })?;
+        self.stage(id, Some(entry));
+        Ok(persisted)
+    }
+
+    /// Keeps a change until its record is written, the record must have just been appended.
+    fn stage(&mut self, id: EntryId, entry: Option<RawEntry>) {
+        if self.wal.is_none() {
+            apply_change(&mut self.entries, id, entry);
+            return;
+        }
+
+        self.pending.push_back(PendingChange {
+            record: self.queued,
+            id,
+            entry,
+        });
+        self.queued += 1;
+    }
+
+    /// Queues a record for the write-ahead log.
+    ///
+    /// Once a record cannot be written, the changes in memory are ahead of the log, therefore all
+    /// the following operations are rejected.
+    fn append(&mut self, record: &WalRecord<&RawEntry>) -> Result<Persisted, Error> {
+        let Some(wal) = &self.wal else {
+            return Ok(Persisted(None));
+        };
+
+        if wal.failed.load(Ordering::Acquire) {
+            return Err(Error::Storage(
+                "a previous change could not be written".to_owned(),
+            ));
+        }
+        let line = record_line(record).map_err(|error| Error::Storage(error.to_string()))?;
+        let (persisted, receiver) = oneshot::channel();
+        wal.send(PendingRecord { line, persisted })?;
+        Ok(Persisted(Some(receiver)))
+    }
+}
+
+/// A record waiting to be written by the [`WalWriter`].
+#[derive(Debug)]
+struct PendingRecord {
+    line: Vec<u8>,
+    persisted: oneshot::Sender<Result<(), String>>,
+}
+
+/// The thread appending the records to the write-ahead log.
+#[derive(Debug)]
+struct WalWriter {
+    sender: Option<mpsc::Sender<PendingRecord>>,
+    thread: Option<JoinHandle<()>>,
+
+    /// The number of records written.
+    written: Arc<AtomicU64>,
+
+    /// Whether a record could not be written.
+    failed: Arc<AtomicBool>,
+}
+
+impl WalWriter {
+    fn spawn(wal: File, wal_len: u64) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let written = Arc::new(AtomicU64::new(0));
+        let failed = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("write-ahead-log".to_owned())
+            .spawn({
+                let written = Arc::clone(&written);
+                let failed = Arc::clone(&failed);
+                move || write_records(wal, wal_len, &receiver, &written, &failed)
+            })?;
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            written,
+            failed,
+        })
+    }
+
+    fn send(&self, record: PendingRecord) -> Result<(), Error> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(record).ok())
+            .ok_or_else(|| Error::Storage("the write-ahead log writer stopped".to_owned()))
+    }
+}
+
+impl Drop for WalWriter {
+    /// Waits for the queued records to be written.
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                warn!("the write-ahead log writer panicked");
+            }
+        }
+    }
+}
+
+/// Writes the records until the [`WalWriter`] is dropped.
+///
+/// The records queued while waiting for the disk are written together, waiting for the disk once.
+/// On failure the log is truncated to its previous length, so that a partial record is never left
+/// behind.
+fn write_records(
+    mut wal: File,
+    mut wal_len: u64,
+    receiver: &mpsc::Receiver<PendingRecord>,
+    written: &AtomicU64,
+    failed: &AtomicBool,
+) {
+    while let Ok(record) = receiver.recv() {
+        let batch: Vec<_> = iter::once(record).chain(receiver.try_iter()).collect();
+        let lines: Vec<u8> = batch
+            .iter()
+            .flat_map(|record| &record.line)
+            .copied()
+            .collect();
+
+        let result = if failed.load(Ordering::Acquire) {
+            Err("a previous change could not be written".to_owned())
+        } else {
+            match wal.write_all(&lines).and_then(|()| wal.sync_data()) {
+                Ok(()) => {
+                    wal_len += u64::try_from(lines.len()).unwrap();
+                    written.fetch_add(u64::try_from(batch.len()).unwrap(), Ordering::Release);
+                    Ok(())
+                }
+                Err(error) => {
+                    failed.store(true, Ordering::Release);
+                    if let Err(error) = wal.set_len(wal_len) {
+                        warn!("unable to restore the write-ahead log length: {error}");
+                    }
+                    Err(error.to_string())
+                }
+            }
+        };
+
+        for record in batch {
+            // The request could have been dropped in the meantime.
+            let _ = record.persisted.send(result.clone());
+        }
+    }
+}
+
+/// Applies a change to the entries, an entry is deleted if missing.
+fn apply_change(entries: &mut BTreeMap<EntryId, RawEntry>, id: EntryId, entry: Option<RawEntry>) {
+    match entry {
+        Some(entry) => entries.insert(id, entry),
+        None => entries.remove(&id),
+    };
+}
+
+/// Replays the records of the write-ahead log over the entries, checking that the log was written
+/// over the same base database.
+///
+/// Returns the length of the valid records, a truncated last record is discarded.
+fn replay(
+    wal: &File,
+    base_checksum: &str,
+    entries: &mut BTreeMap<EntryId, RawEntry>,
+    next_id: &mut u64,
+) -> io::Result<u64> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut reader = BufReader::new(wal);
+    let mut wal_len = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+
+        let record: Option<WalRecord> = line
+            .ends_with('\n')
+            .then(|| serde_json::from_str(&line).ok())
+            .flatten();
+        let Some(record) = record else {
+            if reader.fill_buf()?.is_empty().not() {
+                return Err(invalid(format!(
+                    "invalid write-ahead log record at byte {wal_len}"
+                )));
+            }
+
+            warn!("discarding truncated write-ahead log record at byte {wal_len}");
+            break;
+        };
+
+        match record {
+            WalRecord::Header {
+                base_checksum: checksum,
+            } if wal_len == 0 => {
+                if checksum != base_checksum {
+                    return Err(invalid(format!(
+                        "the write-ahead log was written over a different base database \
+                         (checksum {checksum} instead of {base_checksum}), its changes must be \
+                         merged into the new database before removing it"
+                    )));
+                }
+            }
+            WalRecord::Header { .. } => {
+                return Err(invalid(format!(
+                    "unexpected write-ahead log header at byte {wal_len}"
+                )));
+            }
+            _ if wal_len == 0 => {
+                return Err(invalid("the write-ahead log has no header".to_owned()));
+            }
+            WalRecord::Put { id, mut entry } => {
+                *next_id = (*next_id).max(id.0 + 1);
+                entry.id = Some(id);
+                entries.insert(id, entry);
+            }
+            WalRecord::Delete { id } => {
+                // A deleted identifier is never assigned again.
+                *next_id = (*next_id).max(id.0 + 1);
+                entries.remove(&id);
+            }
+        }
+        wal_len += u64::try_from(read).unwrap();
+    }
+
+    Ok(wal_len)
+}
+
+/// Returns the shortest log leading from the base database to the given entries.
+fn compact(
+    base_checksum: String,
+    base: &BTreeMap<EntryId, RawEntry>,
+    entries: &BTreeMap<EntryId, RawEntry>,
+    next_id: u64,
+) -> serde_json::Result<Vec<u8>> {
+    let mut lines = record_line(&WalRecord::Header { base_checksum })?;
+    for (&id, entry) in entries {
+        if base.get(&id) != Some(entry) {
+            lines.extend(record_line(&WalRecord::Put { id, entry })?);
+        }
+    }
+    for &id in base.keys() {
+        if entries.contains_key(&id).not() {
+            lines.extend(record_line(&WalRecord::<&RawEntry>::Delete { id })?);
+        }
+    }
+
+    // The last identifier is kept even if its entry was deleted, so that it is not reused.
+    let last_id = next_id.checked_sub(1).map(EntryId);
+    if let Some(id) =
+        last_id.filter(|id| entries.contains_key(id).not() && base.contains_key(id).not())
+    {
+        lines.extend(record_line(&WalRecord::<&RawEntry>::Delete { id })?);
+    }
+
+    Ok(lines)
+}
+
+/// Atomically replaces the write-ahead log with the given content, returning the new log.
+fn replace_wal(wal_path: &Path, content: &[u8]) -> io::Result<File> {
+    let mut temp_path = PathBuf::from(wal_path);
+    temp_path.as_mut_os_string().push(".compacting");
+
+    let mut temp = File::create(&temp_path)?;
+    temp.write_all(content)?;
+    temp.sync_all()?;
+    drop(temp);
+    fs::rename(&temp_path, wal_path)?;
+
+    OpenOptions::new().read(true).append(true).open(wal_path)
+}
+
+/// Serializes a record as a line of the write-ahead log.
+fn record_line(record: &WalRecord<&RawEntry>) -> serde_json::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Assigns the identifiers to the entries of the base database.
+///
+/// The entries keep the identifier stored in the database, if any. The other ones are numbered
+/// following their order, after the largest stored identifier.
+fn base_entries(base: Vec<RawEntry>) -> io::Result<BTreeMap<EntryId, RawEntry>> {
+    let mut next_id = base
+        .iter()
+        .filter_map(|entry| entry.id)
+        .map(|id| id.0 + 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut entries = BTreeMap::new();
+    for mut entry in base {
+        let id = *entry.id.get_or_insert_with(|| {
+            next_id += 1;
+            EntryId(next_id - 1)
+        });
+        if entries.insert(id, entry).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the identifier {id} is used by more than one entry"),
+            ));
+        }
+    }
+    Ok(entries)
+}
+
+/// Returns the identifier of the next new entry.
+fn next_id(entries: &BTreeMap<EntryId, RawEntry>) -> u64 {
+    entries.last_key_value().map_or(0, |(id, _)| id.0 + 1)
+}
+
+/// Returns the checksum of the base database, as stored in the header of the write-ahead log.
+fn base_checksum(base: &[RawEntry]) -> serde_json::Result<String> {
+    let mut hasher = Fnv1a::default();
+    for entry in base {
+        hasher.write(&serde_json::to_vec(entry)?);
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Checks that the identifier in the entry, if any, is the expected one.
+fn check_id(id: EntryId, entry: &RawEntry) -> Result<(), Error> {
+    match entry.id {
+        Some(entry_id) if entry_id != id => Err(Error::InvalidEntry(format!(
+            "the identifier {entry_id} does not match the requested one"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the entry can be converted to an [`Entry`] and that its polygons are valid.
+fn validate(entry: &RawEntry) -> Result<(), Error> {
+    Entry::try_from(entry.clone()).map_err(|error| Error::InvalidEntry(error.to_string()))?;
+
+    let GeoShape::Feature(feature) = &entry.geo_shape;
+    let errors = feature.geometry.validate();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        Err(Error::InvalidEntry(format!(
+            "invalid geo_shape: {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::database::fixtures::dataset;
+
+    fn wal_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("wal-test-{name}-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    async fn apply(store: &mut Store, operation: WriteOperation) -> WriteOutcome {
+        let (outcome, persisted) = store.apply(operation).unwrap();
+        persisted.wait().await.unwrap();
+        store.commit_persisted();
+        outcome
+    }
+
+    fn names(store: &Store) -> Vec<(u64, String)> {
+        store
+            .page(0, u16::MAX)
+            .map(|entry| (entry.id.unwrap().0, entry.name.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replay_after_reopen() {
+        let path = wal_path("replay");
+        let mut store = Store::open(dataset(3), &path).unwrap();
+
+        let mut entry = dataset(4).pop().unwrap();
+        let WriteOutcome::Created(id) =
+            apply(&mut store, WriteOperation::Create(entry.clone())).await
+        else {
+            panic!("the entry should be created");
+        };
+        assert_eq!(id, EntryId(3));
+        entry.name = "Replaced".to_owned();
+        apply(&mut store, WriteOperation::Replace(EntryId(0), entry)).await;
+        apply(&mut store, WriteOperation::Delete(EntryId(1))).await;
+        let expected = names(&store);
+        drop(store);
+
+        let store = Store::open(dataset(3), &path).unwrap();
+        assert_eq!(names(&store), expected);
+        assert_eq!(
+            expected,
+            [
+                (0, "Replaced".to_owned()),
+                (2, "Entry 2".to_owned()),
+                (3, "Entry 3".to_owned())
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_record_is_discarded() {
+        let path = wal_path("truncated");
+        let mut store = Store::open(dataset(2), &path).unwrap();
+        apply(&mut store, WriteOperation::Delete(EntryId(0))).await;
+        drop(store);
+
+        let valid_len = fs::metadata(&path).unwrap().len();
+        let mut wal = OpenOptions::new().append(true).open(&path).unwrap();
+        wal.write_all(br#"{"op":"delete","id":"#).unwrap();
+        drop(wal);
+
+        let store = Store::open(dataset(2), &path).unwrap();
+        assert_eq!(names(&store), [(1, "Entry 1".to_owned())]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_len);
+
+        // A broken record followed by other ones is not the result of a crash.
+        drop(store);
+        fs::write(&path, "{}\n{}\n").unwrap();
+        assert_eq!(
+            Store::open(dataset(2), &path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn operations_are_replayed_in_order() {
+        let path = wal_path("order");
+        let mut store = Store::open(dataset(2), &path).unwrap();
+
+        let mut entry = dataset(1).pop().unwrap();
+        entry.name = "Replaced".to_owned();
+        apply(&mut store, WriteOperation::Replace(EntryId(0), entry)).await;
+        let fields = json!({ "piani": "4" }).as_object().unwrap().clone();
+        apply(&mut store, WriteOperation::Patch(EntryId(0), fields)).await;
+        apply(&mut store, WriteOperation::Delete(EntryId(1))).await;
+        let WriteOutcome::Created(id) = apply(
+            &mut store,
+            WriteOperation::Create(dataset(1).pop().unwrap()),
+        )
+        .await
+        else {
+            panic!("the entry should be created");
+        };
+        apply(&mut store, WriteOperation::Delete(id)).await;
+        drop(store);
+        let wal_len = fs::metadata(&path).unwrap().len();
+
+        let mut store = Store::open(dataset(2), &path).unwrap();
+        let entry = store.get(EntryId(0)).unwrap();
+        assert_eq!(
+            (entry.name.as_str(), entry.piani.as_str()),
+            ("Replaced", "4")
+        );
+        assert!(store.get(EntryId(1)).is_err());
+        assert!(store.get(id).is_err());
+
+        // The log is compacted, but the identifiers of the deleted entries are not reused.
+        assert!(fs::metadata(&path).unwrap().len() < wal_len);
+        let WriteOutcome::Created(new_id) = apply(
+            &mut store,
+            WriteOperation::Create(dataset(1).pop().unwrap()),
+        )
+        .await
+        else {
+            panic!("the entry should be created");
+        };
+        assert!(new_id > id);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn changes_are_visible_once_persisted() {
+        let path = wal_path("visible");
+        let mut store = Store::open(dataset(2), &path).unwrap();
+
+        let (_, deleted) = store.apply(WriteOperation::Delete(EntryId(0))).unwrap();
+        assert!(store.get(EntryId(0)).is_ok());
+        // The following operations see the change.
+        assert!(store.apply(WriteOperation::Delete(EntryId(0))).is_err());
+        let (_, created) = store
+            .apply(WriteOperation::Create(dataset(1).pop().unwrap()))
+            .unwrap();
+
+        deleted.wait().await.unwrap();
+        created.wait().await.unwrap();
+        assert_eq!(names(&store).len(), 2);
+        store.commit_persisted();
+        assert_eq!(
+            names(&store),
+            [(1, "Entry 1".to_owned()), (2, "Entry 0".to_owned())]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn different_base_is_refused() {
+        let path = wal_path("base");
+        let mut store = Store::open(dataset(2), &path).unwrap();
+        apply(&mut store, WriteOperation::Delete(EntryId(0))).await;
+        drop(store);
+
+        let mut reordered = dataset(2);
+        reordered.reverse();
+        for base in [dataset(3), reordered] {
+            assert_eq!(
+                Store::open(base, &path).unwrap_err().kind(),
+                io::ErrorKind::InvalidData
+            );
+        }
+        assert!(Store::open(dataset(2), &path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stored_identifiers_are_kept() {
+        let mut base = dataset(3);
+        base[1].id = Some(EntryId(10));
+        let store = Store::in_memory(base.clone()).unwrap();
+        assert_eq!(
+            names(&store),
+            [
+                (10, "Entry 1".to_owned()),
+                (11, "Entry 0".to_owned()),
+                (12, "Entry 2".to_owned())
+            ]
+        );
+        assert_eq!(store.next_id, 13);
+
+        base[2].id = Some(EntryId(10));
+        assert!(Store::in_memory(base).is_err());
+    }
+}
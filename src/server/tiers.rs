@@ -0,0 +1,429 @@
+#![warn(clippy::pedantic)]
+
+//! The tiers of the clients, assigned through their API keys.
+//!
+//! The API key is sent in the [`API_KEY_HEADER`] header. Each key identifies a client, whose bucket
+//! is named after the client rather than after the key, and assigns it a [`Tier`]. The requests
+//! without a key belong to the anonymous tier and are identified by their IP address, while the
+//! requests with an unknown key are rejected.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    net::IpAddr,
+    ops::Not,
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    buckets::{ClientId, UNKNOWN_CLIENT},
+    error::Error,
+};
+use crate::{
+    database::{ServerField, ServerQuery, DEFAULT_PAGE_SIZE},
+    API_KEY_HEADER,
+};
+
+/// The API keys accepted by the server, and the tiers of their clients.
+///
+/// The configuration can be deserialized, for example:
+///
+/// ```json
+/// {
+///   "anonymous": {
+///     "capacity": 100,
+///     "leak_per_second": 1,
+///     "allowed_fields": ["id", "name"],
+///     "max_page_size": 5
+///   },
+///   "tiers": {
+///     "free": { "capacity": 500, "leak_per_second": 4, "max_page_size": 20 },
+///     "premium": {
+///       "capacity": 5000,
+///       "leak_per_second": 50,
+///       "cost_multiplier": 0.5,
+///       "can_write": true
+///     }
+///   },
+///   "keys": {
+///     "0123456789abcdef": { "client": "acme", "tier": "premium" }
+///   }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeys {
+    /// The tier of the requests without an API key.
+    pub anonymous: Tier,
+
+    /// The tiers, by name.
+    #[serde(default)]
+    pub tiers: BTreeMap<String, Tier>,
+
+    /// The clients, by API key.
+    #[serde(default)]
+    pub keys: BTreeMap<String, ApiKey>,
+}
+
+/// The client identified by an API key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    /// The name of the client, which identifies its bucket.
+    ///
+    /// It cannot be an IP address, or the name of the unknown clients, since the buckets of the
+    /// anonymous requests are named after them.
+    pub client: String,
+
+    /// The name of the tier of the client, in [`ApiKeys::tiers`].
+    pub tier: String,
+}
+
+/// The parameters and the restrictions of the buckets of a group of clients.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tier {
+    /// The capacity of the buckets.
+    pub capacity: u16,
+
+    /// The leak per second of the buckets.
+    pub leak_per_second: u8,
+
+    /// The factor applied to the cost of each operation, rounding up, see [`ScaledCost`].
+    ///
+    /// [`ScaledCost`]: crate::database::ScaledCost
+    #[serde(default = "default_cost_multiplier")]
+    pub cost_multiplier: f64,
+
+    /// The fields which can be requested. If missing, all the fields are allowed.
+    ///
+    /// A query without fields requests all of them, therefore it is rejected when the fields are
+    /// restricted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_fields: Option<BTreeSet<ServerField>>,
+
+    /// The largest page which can be requested. If missing, there is no limit.
+    ///
+    /// A query without a page size requests [`DEFAULT_PAGE_SIZE`] entries, which is checked as
+    /// well.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_page_size: Option<u16>,
+
+    /// Whether the entries can be created, changed and deleted, `false` by default.
+    #[serde(default)]
+    pub can_write: bool,
+}
+
+const fn default_cost_multiplier() -> f64 {
+    1.
+}
+
+impl ApiKeys {
+    /// Creates the configuration with the given anonymous tier and no API keys.
+    #[must_use]
+    pub fn new(anonymous: Tier) -> Self {
+        Self {
+            anonymous,
+            tiers: BTreeMap::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a tier, replacing the one with the same name.
+    #[must_use]
+    pub fn with_tier(mut self, name: impl Into<String>, tier: Tier) -> Self {
+        self.tiers.insert(name.into(), tier);
+        self
+    }
+
+    /// Adds an API key, assigning the given client to the given tier.
+    #[must_use]
+    pub fn with_key(
+        mut self,
+        key: impl Into<String>,
+        client: impl Into<String>,
+        tier: impl Into<String>,
+    ) -> Self {
+        self.keys.insert(
+            key.into(),
+            ApiKey {
+                client: client.into(),
+                tier: tier.into(),
+            },
+        );
+        self
+    }
+}
+
+impl Tier {
+    /// Creates a read-only tier with the given bucket parameters, without restrictions on the
+    /// queries.
+    #[must_use]
+    pub const fn new(capacity: u16, leak_per_second: u8) -> Self {
+        Self {
+            capacity,
+            leak_per_second,
+            cost_multiplier: default_cost_multiplier(),
+            allowed_fields: None,
+            max_page_size: None,
+            can_write: false,
+        }
+    }
+
+    /// Sets the factor applied to the cost of each operation.
+    #[must_use]
+    pub const fn with_cost_multiplier(mut self, cost_multiplier: f64) -> Self {
+        self.cost_multiplier = cost_multiplier;
+        self
+    }
+
+    /// Restricts the fields which can be requested.
+    #[must_use]
+    pub fn with_allowed_fields(mut self, fields: impl IntoIterator<Item = ServerField>) -> Self {
+        self.allowed_fields = Some(fields.into_iter().collect());
+        self
+    }
+
+    /// Restricts the size of the pages.
+    #[must_use]
+    pub const fn with_max_page_size(mut self, max_page_size: u16) -> Self {
+        self.max_page_size = Some(max_page_size);
+        self
+    }
+
+    /// Allows the entries to be created, changed and deleted.
+    #[must_use]
+    pub const fn with_writes(mut self) -> Self {
+        self.can_write = true;
+        self
+    }
+
+    /// Checks that the requested fields are allowed.
+    pub(super) fn check_fields(&self, fields: &BTreeSet<ServerField>) -> Result<(), Error> {
+        match &self.allowed_fields {
+            Some(allowed) if fields.is_empty() || fields.is_subset(allowed).not() => {
+                Err(Error::FieldsNotAllowed(allowed.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the requested fields and page size are allowed.
+    pub(super) fn check_query(&self, query: &ServerQuery) -> Result<(), Error> {
+        self.check_fields(&query.fields)?;
+
+        let page_size = query.page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+        match self.max_page_size {
+            Some(max) if page_size > max => Err(Error::PageSizeTooLarge { page_size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the entries can be changed.
+    pub(super) fn check_write(&self) -> Result<(), Error> {
+        if self.can_write {
+            Ok(())
+        } else {
+            Err(Error::WriteNotAllowed)
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.capacity == 0 {
+            Err("the capacity must be positive".to_owned())
+        } else if self.leak_per_second == 0 {
+            // A full bucket would never empty.
+            Err("the leak per second must be positive".to_owned())
+        } else if self.cost_multiplier.is_finite().not() || self.cost_multiplier < 0. {
+            Err(format!("invalid cost multiplier {}", self.cost_multiplier))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The API keys and the tiers while the server runs.
+#[derive(Debug)]
+pub(super) struct Authenticator {
+    anonymous: Arc<Tier>,
+    keys: HashMap<String, (ClientId, Arc<Tier>)>,
+}
+
+impl Authenticator {
+    /// Checks the configuration, sharing each tier among its clients.
+    ///
+    /// Returns an error if a tier is not valid, or an API key refers to a missing tier or has a
+    /// client named like the anonymous ones.
+    pub fn new(api_keys: ApiKeys) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+        api_keys
+            .anonymous
+            .validate()
+            .map_err(|error| invalid(format!("anonymous tier: {error}")))?;
+        let tiers = api_keys
+            .tiers
+            .into_iter()
+            .map(|(name, tier)| match tier.validate() {
+                Ok(()) => Ok((name, Arc::new(tier))),
+                Err(error) => Err(invalid(format!("tier {name}: {error}"))),
+            })
+            .collect::<io::Result<BTreeMap<_, _>>>()?;
+
+        let keys = api_keys
+            .keys
+            .into_iter()
+            .map(|(key, ApiKey { client, tier })| {
+                if client.parse::<IpAddr>().is_ok() || client == UNKNOWN_CLIENT {
+                    return Err(invalid(format!(
+                        "the client {client} would share the bucket of anonymous requests"
+                    )));
+                }
+
+                match tiers.get(&tier) {
+                    Some(tier) => Ok((key, (ClientId(client), Arc::clone(tier)))),
+                    None => Err(invalid(format!(
+                        "the key of {client} refers to the missing tier {tier}"
+                    ))),
+                }
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            anonymous: Arc::new(api_keys.anonymous),
+            keys,
+        })
+    }
+
+    /// Returns the tier of the requests without an API key.
+    pub fn anonymous(&self) -> &Arc<Tier> {
+        &self.anonymous
+    }
+}
+
+/// The client of a request, with its tier.
+///
+/// The extraction fails if the API key is unknown.
+#[derive(Clone, Debug)]
+pub(super) struct Caller {
+    pub client: ClientId,
+    pub tier: Arc<Tier>,
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Caller {
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let authenticator = request
+            .extensions()
+            .get::<Arc<Authenticator>>()
+            .expect("the authenticator should be an extension of the router");
+
+        let Some(key) = request.headers().get(API_KEY_HEADER) else {
+            return Ok(Self {
+                client: ClientId::from_connection(request.extensions()),
+                tier: Arc::clone(&authenticator.anonymous),
+            });
+        };
+
+        key.to_str()
+            .ok()
+            .and_then(|key| authenticator.keys.get(key))
+            .map(|(client, tier)| Self {
+                client: client.clone(),
+                tier: Arc::clone(tier),
+            })
+            .ok_or(Error::InvalidApiKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_query() {
+        let tier = Tier::new(100, 1)
+            .with_allowed_fields([ServerField::Id, ServerField::Name])
+            .with_max_page_size(5);
+        let query = |fields: &[ServerField], page_size| ServerQuery {
+            fields: fields.iter().copied().collect(),
+            page_size,
+            ..ServerQuery::default()
+        };
+
+        assert_eq!(
+            tier.check_query(&query(&[ServerField::Name], Some(5))),
+            Ok(())
+        );
+        assert_eq!(
+            tier.check_query(&query(&[ServerField::Name, ServerField::GeoShape], Some(5))),
+            Err(Error::FieldsNotAllowed(
+                [ServerField::Id, ServerField::Name].into()
+            ))
+        );
+        assert!(tier.check_query(&query(&[], Some(5))).is_err());
+        assert_eq!(
+            tier.check_query(&query(&[ServerField::Id], None)),
+            Err(Error::PageSizeTooLarge {
+                page_size: DEFAULT_PAGE_SIZE,
+                max: 5
+            })
+        );
+        assert_eq!(Tier::new(100, 1).check_query(&query(&[], None)), Ok(()));
+    }
+
+    #[test]
+    fn check_write() {
+        assert_eq!(Tier::new(100, 1).check_write(), Err(Error::WriteNotAllowed));
+        assert_eq!(Tier::new(100, 1).with_writes().check_write(), Ok(()));
+    }
+
+    #[test]
+    fn authenticator() {
+        let api_keys: ApiKeys = serde_json::from_str(
+            r#"{
+                "anonymous": { "capacity": 100, "leak_per_second": 1, "allowed_fields": ["id"] },
+                "tiers": { "premium": { "capacity": 5000, "leak_per_second": 50 } },
+                "keys": { "secret": { "client": "acme", "tier": "premium" } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            api_keys,
+            ApiKeys::new(Tier::new(100, 1).with_allowed_fields([ServerField::Id]))
+                .with_tier("premium", Tier::new(5000, 50))
+                .with_key("secret", "acme", "premium")
+        );
+
+        let authenticator = Authenticator::new(api_keys.clone()).unwrap();
+        let (client, tier) = &authenticator.keys["secret"];
+        assert_eq!(client.0, "acme");
+        assert_eq!(tier.capacity, 5000);
+        assert_eq!(authenticator.anonymous().capacity, 100);
+
+        let missing_tier = api_keys.clone().with_key("other", "someone", "free");
+        assert!(Authenticator::new(missing_tier).is_err());
+        for client in ["127.0.0.1", "::1", UNKNOWN_CLIENT] {
+            let anonymous_client = api_keys.clone().with_key("other", client, "premium");
+            assert!(Authenticator::new(anonymous_client).is_err());
+        }
+        for tier in [
+            Tier::new(5000, 50).with_cost_multiplier(-1.),
+            Tier::new(0, 50),
+            Tier::new(5000, 0),
+        ] {
+            let invalid_tier = api_keys.clone().with_tier("premium", tier.clone());
+            assert!(Authenticator::new(invalid_tier).is_err(), "{tier:?}");
+        }
+        let invalid_anonymous = ApiKeys {
+            anonymous: Tier::new(100, 0),
+            ..api_keys
+        };
+        assert!(Authenticator::new(invalid_anonymous).is_err());
+    }
+}